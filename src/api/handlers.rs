@@ -1,11 +1,18 @@
+use crate::api::validation::{
+    parse_amendment, parse_listing, parse_order, prepare_order, validate_amendment,
+    validate_listing, validate_order, FieldError,
+};
+use crate::db::errors::MarketError;
 use crate::db::traits::MarketDatabase;
-use crate::market::interfaces::{Listing, Order};
+use crate::market::interfaces::TradeStatus;
 use futures::lock::Mutex;
+use serde_json::Value;
 use std::sync::Arc;
 use valence_core::api::errors::ApiErrorType;
 use valence_core::api::interfaces::CFilterConnection;
 use valence_core::api::responses::{json_serialize_embed, CallResponse, JsonReply};
 use valence_core::db::handler::KvStoreConnection;
+use warp::hyper::StatusCode;
 
 /// Handles retrieving all listings
 ///
@@ -22,12 +29,11 @@ pub async fn listings_handler<
 ) -> Result<JsonReply, JsonReply> {
     let r = CallResponse::new("listings");
 
-    let db_lock = db.lock().await;
-    let listings = match db_lock.get_listings().await {
+    // The connection is cloned out so that the lock isn't held for the whole call
+    let db = db.lock().await.clone();
+    let listings = match db.get_listings().await {
         Ok(listings) => listings,
-        Err(_e) => {
-            return r.into_err_internal(ApiErrorType::DBInsertionFailed);
-        }
+        Err(e) => return r.into_err(e.status(), e.api_error_type()),
     };
 
     r.into_ok(
@@ -36,11 +42,12 @@ pub async fn listings_handler<
     )
 }
 
-/// Handles adding a listing to the database
+/// Handles adding a listing to the database. Invalid listings, including those with
+/// malformed amounts, are rejected with a `400` listing each field at fault
 ///
 /// ### Arguments
 ///
-/// * `payload` - The listing to add
+/// * `payload` - The raw listing to add
 /// * `db` - The database connection to use
 /// * `cache` - The cache connection to use
 /// * `cf` - The cuckoo filter connection to use
@@ -48,16 +55,36 @@ pub async fn listing_send_handler<
     D: MarketDatabase + Clone + Send,
     C: KvStoreConnection + Clone + Send,
 >(
-    payload: Listing,
+    payload: Value,
     db: Arc<Mutex<D>>,
     _cache: Arc<Mutex<C>>,
 ) -> Result<JsonReply, JsonReply> {
     let r = CallResponse::new("listing_send");
 
-    let db_lock = db.lock().await;
-    match db_lock.add_listing(payload.clone()).await {
+    let payload = match parse_listing(payload) {
+        Ok(listing) => listing,
+        Err(errors) => {
+            return r.into_err_with_data(
+                StatusCode::BAD_REQUEST,
+                ApiErrorType::Generic("Invalid listing".to_string()),
+                json_serialize_embed(errors),
+            )
+        }
+    };
+
+    let errors = validate_listing(&payload);
+    if !errors.is_empty() {
+        return r.into_err_with_data(
+            StatusCode::BAD_REQUEST,
+            ApiErrorType::Generic("Invalid listing".to_string()),
+            json_serialize_embed(errors),
+        );
+    }
+
+    let db = db.lock().await.clone();
+    match db.add_listing(payload.clone()).await {
         Ok(_) => r.into_ok("Listing added successfully", json_serialize_embed(payload)),
-        Err(_) => r.into_err_internal(ApiErrorType::DBInsertionFailed),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
     }
 }
 
@@ -80,17 +107,18 @@ pub async fn listing_by_id_handler<
 ) -> Result<JsonReply, JsonReply> {
     let r = CallResponse::new("listing_by_id");
 
-    let db_lock = db.lock().await;
-    match db_lock.get_listing_by_id(id).await {
+    let db = db.lock().await.clone();
+    match db.get_listing_by_id(id).await {
         Ok(listing) => r.into_ok(
             "Listing retrieved successfully",
             json_serialize_embed(listing),
         ),
-        Err(_) => r.into_err_internal(ApiErrorType::DBInsertionFailed),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
     }
 }
 
-/// Handles retrieving orders by their listing ID
+/// Handles retrieving orders by their listing ID. Only the displayed quantity of
+/// iceberg orders is returned
 ///
 /// ### Arguments
 ///
@@ -109,13 +137,13 @@ pub async fn orders_by_id_handler<
 ) -> Result<JsonReply, JsonReply> {
     let r = CallResponse::new("orders_by_id");
 
-    let db_lock = db.lock().await;
-    match db_lock.get_orders_by_id(id).await {
+    let db = db.lock().await.clone();
+    match db.get_orders_by_id(id).await {
         Ok(orders) => r.into_ok(
             "Orders retrieved successfully",
-            json_serialize_embed(orders),
+            json_serialize_embed(orders.public_view()),
         ),
-        Err(_) => r.into_err_internal(ApiErrorType::DBInsertionFailed),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
     }
 }
 
@@ -138,21 +166,25 @@ pub async fn orders_pending_handler<
 ) -> Result<JsonReply, JsonReply> {
     let r = CallResponse::new("orders_pending");
 
-    let db_lock = db.lock().await;
-    match db_lock.get_pending_trades_by_id(id).await {
+    let db = db.lock().await.clone();
+    match db.get_pending_trades_by_id(id).await {
         Ok(pending_trades) => r.into_ok(
             "Pending trades retrieved successfully",
             json_serialize_embed(pending_trades),
         ),
-        Err(_) => r.into_err_internal(ApiErrorType::DBInsertionFailed),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
     }
 }
 
-/// Handles adding an order to the database
+/// Handles adding an order to the database. Invalid orders, including those with malformed
+/// amounts, for an unknown listing or that break the listing's tick size, lot size, minimum quantity or minimum
+/// notional, are rejected with a `400` listing each field at fault. The server assigns
+/// the order's ID and creation time. The response reports the quantity that filled,
+/// rested and was discarded, along with any pending trades created
 ///
 /// ### Arguments
 ///
-/// * `payload` - The order to add
+/// * `payload` - The raw order to add
 /// * `db` - The database connection to use
 /// * `cache` - The cache connection to use
 /// * `cf` - The cuckoo filter connection to use
@@ -160,16 +192,199 @@ pub async fn orders_send_handler<
     D: MarketDatabase + Clone + Send,
     C: KvStoreConnection + Clone + Send,
 >(
-    payload: Order,
+    payload: Value,
     db: Arc<Mutex<D>>,
     _cache: Arc<Mutex<C>>,
     _cf: CFilterConnection,
 ) -> Result<JsonReply, JsonReply> {
     let r = CallResponse::new("orders_send");
+    let payload = match parse_order(payload) {
+        Ok(order) => order,
+        Err(errors) => {
+            return r.into_err_with_data(
+                StatusCode::BAD_REQUEST,
+                ApiErrorType::Generic("Invalid order".to_string()),
+                json_serialize_embed(errors),
+            )
+        }
+    };
+    let mut errors = validate_order(&payload);
+
+    let db = db.lock().await.clone();
+    if errors.is_empty() {
+        match db.get_listing_by_id(payload.listing_id.clone()).await {
+            // Orders that don't conform to the listing's trading rules never reach the orderbook
+            Ok(listing) => errors.extend(listing.check_order(&payload).err().map(FieldError::from)),
+            Err(MarketError::NotFound(_)) => {
+                errors.push(FieldError::new(
+                    "listing_id",
+                    "No listing exists with this ID",
+                ));
+            }
+            Err(e) => return r.into_err(e.status(), e.api_error_type()),
+        }
+    }
+
+    if !errors.is_empty() {
+        return r.into_err_with_data(
+            StatusCode::BAD_REQUEST,
+            ApiErrorType::Generic("Invalid order".to_string()),
+            json_serialize_embed(errors),
+        );
+    }
+
+    match db.add_order(prepare_order(payload)).await {
+        Ok(outcome) => r.into_ok("Order added successfully", json_serialize_embed(outcome)),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
+    }
+}
+
+/// Handles cancelling a resting order on a listing
+///
+/// ### Arguments
+///
+/// * `listing_id` - The ID of the listing the order belongs to
+/// * `order_id` - The ID of the order to cancel
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cf` - The cuckoo filter connection to use
+pub async fn orders_cancel_handler<
+    D: MarketDatabase + Clone + Send,
+    C: KvStoreConnection + Clone + Send,
+>(
+    listing_id: String,
+    order_id: String,
+    db: Arc<Mutex<D>>,
+    _cache: Arc<Mutex<C>>,
+    _cf: CFilterConnection,
+) -> Result<JsonReply, JsonReply> {
+    let r = CallResponse::new("orders_cancel");
+
+    let db = db.lock().await.clone();
+    match db.cancel_order(listing_id, order_id).await {
+        Ok(order) => r.into_ok("Order cancelled successfully", json_serialize_embed(order)),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
+    }
+}
 
-    let db_lock = db.lock().await;
-    match db_lock.add_order(payload.clone()).await {
-        Ok(_) => r.into_ok("Order added successfully", json_serialize_embed(payload)),
-        Err(_) => r.into_err_internal(ApiErrorType::DBInsertionFailed),
+/// Handles amending the price and/or quantity of a resting order on a listing
+///
+/// ### Arguments
+///
+/// * `listing_id` - The ID of the listing the order belongs to
+/// * `order_id` - The ID of the order to amend
+/// * `payload` - The raw new price and/or quantity for the order
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cf` - The cuckoo filter connection to use
+pub async fn orders_amend_handler<
+    D: MarketDatabase + Clone + Send,
+    C: KvStoreConnection + Clone + Send,
+>(
+    listing_id: String,
+    order_id: String,
+    payload: Value,
+    db: Arc<Mutex<D>>,
+    _cache: Arc<Mutex<C>>,
+    _cf: CFilterConnection,
+) -> Result<JsonReply, JsonReply> {
+    let r = CallResponse::new("orders_amend");
+    let payload = match parse_amendment(payload) {
+        Ok(amendment) => amendment,
+        Err(errors) => {
+            return r.into_err_with_data(
+                StatusCode::BAD_REQUEST,
+                ApiErrorType::Generic("Invalid amendment".to_string()),
+                json_serialize_embed(errors),
+            )
+        }
+    };
+
+    let db = db.lock().await.clone();
+    let order_book = match db.get_orders_by_id(listing_id.clone()).await {
+        Ok(order_book) => order_book,
+        Err(e) => return r.into_err(e.status(), e.api_error_type()),
+    };
+
+    // Amendments are held to the same trading rules as new orders. Orders that aren't
+    // resting are left for the database to report as missing
+    let resting = order_book
+        .bids
+        .get(&order_id)
+        .or_else(|| order_book.asks.get(&order_id));
+
+    if let Some(order) = resting {
+        let errors = match db.get_listing_by_id(listing_id.clone()).await {
+            Ok(listing) => validate_amendment(&listing, order, &payload),
+            Err(e) => return r.into_err(e.status(), e.api_error_type()),
+        };
+
+        if !errors.is_empty() {
+            return r.into_err_with_data(
+                StatusCode::BAD_REQUEST,
+                ApiErrorType::Generic("Invalid amendment".to_string()),
+                json_serialize_embed(errors),
+            );
+        }
+    }
+
+    match db.amend_order(listing_id, order_id, payload).await {
+        Ok(order) => r.into_ok("Order amended successfully", json_serialize_embed(order)),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
+    }
+}
+
+/// Handles moving a pending trade to a new settlement state by its DRUID
+///
+/// ### Arguments
+///
+/// * `druid` - The DRUID of the trade
+/// * `status` - The state to move the trade to
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cf` - The cuckoo filter connection to use
+pub async fn trades_status_handler<
+    D: MarketDatabase + Clone + Send,
+    C: KvStoreConnection + Clone + Send,
+>(
+    druid: String,
+    status: TradeStatus,
+    db: Arc<Mutex<D>>,
+    _cache: Arc<Mutex<C>>,
+    _cf: CFilterConnection,
+) -> Result<JsonReply, JsonReply> {
+    let r = CallResponse::new("trades_status");
+
+    let db = db.lock().await.clone();
+    match db.update_trade_status(druid, status).await {
+        Ok(trade) => r.into_ok("Trade updated successfully", json_serialize_embed(trade)),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
+    }
+}
+
+/// Handles retrieving the fees charged on each listing's trades
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cf` - The cuckoo filter connection to use
+pub async fn fees_report_handler<
+    D: MarketDatabase + Clone + Send,
+    C: KvStoreConnection + Clone + Send,
+>(
+    db: Arc<Mutex<D>>,
+    _cache: Arc<Mutex<C>>,
+    _cf: CFilterConnection,
+) -> Result<JsonReply, JsonReply> {
+    let r = CallResponse::new("fees_report");
+
+    let db = db.lock().await.clone();
+    match db.get_fee_reports().await {
+        Ok(reports) => r.into_ok(
+            "Fee reports retrieved successfully",
+            json_serialize_embed(reports),
+        ),
+        Err(e) => r.into_err(e.status(), e.api_error_type()),
     }
 }
@@ -1,2 +1,4 @@
 pub mod handlers;
 pub mod routes;
+pub mod utils;
+pub mod validation;
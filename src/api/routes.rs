@@ -1,15 +1,17 @@
 use crate::api::handlers::{
-    listing_by_id_handler, listing_send_handler, listings_handler, orders_by_id_handler,
-    orders_pending_handler, orders_send_handler,
+    fees_report_handler, listing_by_id_handler, listing_send_handler, listings_handler,
+    orders_amend_handler, orders_by_id_handler, orders_cancel_handler, orders_pending_handler,
+    orders_send_handler, trades_status_handler,
 };
+use crate::api::utils::{delete_cors, put_cors};
 use crate::db::traits::MarketDatabase;
-use crate::market::interfaces::Listing;
+use crate::market::interfaces::TradeStatus;
 use futures::lock::Mutex;
 use std::sync::Arc;
-use warp::{Filter, Rejection, Reply};
 use valence_core::api::interfaces::CFilterConnection;
 use valence_core::api::utils::{get_cors, map_api_res, post_cors, with_node_component};
 use valence_core::db::handler::KvStoreConnection;
+use warp::{Filter, Rejection, Reply};
 
 // ========== LISTING ROUTES ========== //
 
@@ -87,9 +89,7 @@ pub fn listing_send<
         .and(warp::body::json())
         .and(with_node_component(cache))
         .and(with_node_component(db))
-        .and_then(move |data: Listing, cache, db| {
-            map_api_res(listing_send_handler(data, db, cache))
-        })
+        .and_then(move |data, cache, db| map_api_res(listing_send_handler(data, db, cache)))
         .with(post_cors())
 }
 
@@ -178,3 +178,164 @@ pub fn orders_send<
         .and_then(move |data, cache, db, cf| map_api_res(orders_send_handler(data, db, cache, cf)))
         .with(post_cors())
 }
+
+/// DELETE /orders/{listing_id}/{order_id}
+///
+/// Cancels a resting order on a listing
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cuckoo_filter` - The cuckoo filter connection to use
+pub fn orders_cancel<
+    D: MarketDatabase + Clone + Send + Sync + 'static,
+    C: KvStoreConnection + Clone + Send + Sync + 'static,
+>(
+    db: Arc<Mutex<D>>,
+    cache: Arc<Mutex<C>>,
+    cuckoo_filter: CFilterConnection,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("orders" / String / String)
+        .and(warp::delete())
+        .and(with_node_component(cache))
+        .and(with_node_component(db))
+        .and(with_node_component(cuckoo_filter))
+        .and_then(move |listing_id, order_id, cache, db, cf| {
+            map_api_res(orders_cancel_handler(listing_id, order_id, db, cache, cf))
+        })
+        .with(delete_cors())
+}
+
+/// PUT /orders/{listing_id}/{order_id}
+///
+/// Amends the price and/or quantity of a resting order on a listing
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cuckoo_filter` - The cuckoo filter connection to use
+/// * `body_limit` - The maximum size of the request body
+pub fn orders_amend<
+    D: MarketDatabase + Clone + Send + Sync + 'static,
+    C: KvStoreConnection + Clone + Send + Sync + 'static,
+>(
+    db: Arc<Mutex<D>>,
+    cache: Arc<Mutex<C>>,
+    cuckoo_filter: CFilterConnection,
+    body_limit: u64,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("orders" / String / String)
+        .and(warp::put())
+        .and(warp::body::content_length_limit(body_limit))
+        .and(warp::body::json())
+        .and(with_node_component(cache))
+        .and(with_node_component(db))
+        .and(with_node_component(cuckoo_filter))
+        .and_then(move |listing_id, order_id, data, cache, db, cf| {
+            map_api_res(orders_amend_handler(
+                listing_id, order_id, data, db, cache, cf,
+            ))
+        })
+        .with(put_cors())
+}
+
+// ========== TRADE ROUTES ========== //
+
+/// POST /trades/{druid}/confirm
+///
+/// Marks a pending trade as confirmed on chain, removing it from the pending list
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cuckoo_filter` - The cuckoo filter connection to use
+pub fn trades_confirm<
+    D: MarketDatabase + Clone + Send + Sync + 'static,
+    C: KvStoreConnection + Clone + Send + Sync + 'static,
+>(
+    db: Arc<Mutex<D>>,
+    cache: Arc<Mutex<C>>,
+    cuckoo_filter: CFilterConnection,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("trades" / String / "confirm")
+        .and(warp::post())
+        .and(with_node_component(cache))
+        .and(with_node_component(db))
+        .and(with_node_component(cuckoo_filter))
+        .and_then(move |druid, cache, db, cf| {
+            map_api_res(trades_status_handler(
+                druid,
+                TradeStatus::Confirmed,
+                db,
+                cache,
+                cf,
+            ))
+        })
+        .with(post_cors())
+}
+
+/// POST /trades/{druid}/fail
+///
+/// Marks a pending trade as failed, removing it from the pending list and returning
+/// its quantity to the orders it matched
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cuckoo_filter` - The cuckoo filter connection to use
+pub fn trades_fail<
+    D: MarketDatabase + Clone + Send + Sync + 'static,
+    C: KvStoreConnection + Clone + Send + Sync + 'static,
+>(
+    db: Arc<Mutex<D>>,
+    cache: Arc<Mutex<C>>,
+    cuckoo_filter: CFilterConnection,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("trades" / String / "fail")
+        .and(warp::post())
+        .and(with_node_component(cache))
+        .and(with_node_component(db))
+        .and(with_node_component(cuckoo_filter))
+        .and_then(move |druid, cache, db, cf| {
+            map_api_res(trades_status_handler(
+                druid,
+                TradeStatus::Failed,
+                db,
+                cache,
+                cf,
+            ))
+        })
+        .with(post_cors())
+}
+
+// ========== FEE ROUTES ========== //
+
+/// GET /fees
+///
+/// Retrieves the maker and taker fees charged on each listing's trades
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `cache` - The cache connection to use
+/// * `cuckoo_filter` - The cuckoo filter connection to use
+pub fn fees_report<
+    D: MarketDatabase + Clone + Send + Sync + 'static,
+    C: KvStoreConnection + Clone + Send + Sync + 'static,
+>(
+    db: Arc<Mutex<D>>,
+    cache: Arc<Mutex<C>>,
+    cuckoo_filter: CFilterConnection,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("fees")
+        .and(warp::get())
+        .and(with_node_component(db))
+        .and(with_node_component(cache))
+        .and(with_node_component(cuckoo_filter))
+        .and_then(move |db, cache, cf| map_api_res(fees_report_handler(db, cache, cf)))
+        .with(get_cors())
+}
@@ -0,0 +1,37 @@
+/// Easy and simple PUT CORS
+pub fn put_cors() -> warp::cors::Builder {
+    warp::cors()
+        .allow_any_origin()
+        .allow_headers(vec![
+            "Accept",
+            "User-Agent",
+            "Sec-Fetch-Mode",
+            "Referer",
+            "Origin",
+            "Access-Control-Request-Method",
+            "Access-Control-Request-Headers",
+            "Access-Control-Allow-Origin",
+            "Access-Control-Allow-Headers",
+            "Content-Type",
+        ])
+        .allow_methods(vec!["PUT"])
+}
+
+/// Easy and simple DELETE CORS
+pub fn delete_cors() -> warp::cors::Builder {
+    warp::cors()
+        .allow_any_origin()
+        .allow_headers(vec![
+            "Accept",
+            "User-Agent",
+            "Sec-Fetch-Mode",
+            "Referer",
+            "Origin",
+            "Access-Control-Request-Method",
+            "Access-Control-Request-Headers",
+            "Access-Control-Allow-Origin",
+            "Access-Control-Allow-Headers",
+            "Content-Type",
+        ])
+        .allow_methods(vec!["DELETE"])
+}
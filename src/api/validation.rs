@@ -0,0 +1,546 @@
+use crate::constants::MAX_LISTING_DECIMALS;
+use crate::market::amount::Amount;
+use crate::market::interfaces::{Listing, ListingRuleError, Order, OrderAmendment, OrderType};
+use crate::utils::{construct_druid, construct_mongodb_object_id};
+use chrono::prelude::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Amount fields of a listing payload
+const LISTING_AMOUNT_FIELDS: [&str; 6] = [
+    "initial_price",
+    "quantity",
+    "tick_size",
+    "lot_size",
+    "min_quantity",
+    "min_notional",
+];
+
+/// Amount fields of an order payload
+const ORDER_AMOUNT_FIELDS: [&str; 5] = [
+    "price",
+    "quantity",
+    "trigger_price",
+    "display_quantity",
+    "reserve_quantity",
+];
+
+/// Amount fields of an amendment payload
+const AMENDMENT_AMOUNT_FIELDS: [&str; 2] = ["price", "quantity"];
+
+/// A problem with one field of a request payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    /// Creates a new field error
+    ///
+    /// ### Arguments
+    ///
+    /// * `field` - The name of the field at fault
+    /// * `message` - What is wrong with the field
+    pub fn new(field: &str, message: &str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ListingRuleError> for FieldError {
+    fn from(error: ListingRuleError) -> Self {
+        let field = match error {
+            ListingRuleError::OffTick(..) | ListingRuleError::BelowMinNotional(..) => "price",
+            ListingRuleError::TriggerOffTick(..) => "trigger_price",
+            ListingRuleError::OffLot(..) | ListingRuleError::BelowMinQuantity(..) => "quantity",
+        };
+
+        FieldError::new(field, &error.to_string())
+    }
+}
+
+/// Returns true if the ID is a valid MongoDB object ID
+///
+/// ### Arguments
+///
+/// * `id` - The ID to check
+fn is_object_id(id: &str) -> bool {
+    construct_mongodb_object_id(id.to_string()).is_ok()
+}
+
+/// Reports each of the given amounts that is too large to be stored
+///
+/// ### Arguments
+///
+/// * `errors` - The problems found so far
+/// * `amounts` - Each amount along with the name of its field
+fn check_storable(errors: &mut Vec<FieldError>, amounts: &[(&str, Option<Amount>)]) {
+    for (field, amount) in amounts {
+        if amount.map(|amount| amount > Amount::MAX).unwrap_or(false) {
+            errors.push(FieldError::new(
+                field,
+                &format!("Must be at most {}", Amount::MAX),
+            ));
+        }
+    }
+}
+
+/// Deserializes a raw request payload, reporting amounts that aren't a whole,
+/// non-negative number of base units as field errors rather than a rejected body
+///
+/// ### Arguments
+///
+/// * `payload` - The raw JSON payload
+/// * `amount_fields` - The keys holding amounts
+fn parse_payload<T: DeserializeOwned>(
+    payload: Value,
+    amount_fields: &[&str],
+) -> Result<T, Vec<FieldError>> {
+    let fields = match payload.as_object() {
+        Some(fields) => fields,
+        None => return Err(vec![FieldError::new("body", "Must be a JSON object")]),
+    };
+
+    let errors: Vec<FieldError> = amount_fields
+        .iter()
+        .filter(|field| match fields.get(**field) {
+            None | Some(Value::Null) => false,
+            Some(value) => value.as_u64().is_none(),
+        })
+        .map(|field| FieldError::new(field, "Must be a whole, non-negative number of base units"))
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    serde_json::from_value(payload).map_err(|e| vec![FieldError::new("body", &e.to_string())])
+}
+
+/// Deserializes a raw listing payload
+///
+/// ### Arguments
+///
+/// * `payload` - The raw JSON payload
+pub fn parse_listing(payload: Value) -> Result<Listing, Vec<FieldError>> {
+    parse_payload(payload, &LISTING_AMOUNT_FIELDS)
+}
+
+/// Deserializes a raw order payload
+///
+/// ### Arguments
+///
+/// * `payload` - The raw JSON payload
+pub fn parse_order(payload: Value) -> Result<Order, Vec<FieldError>> {
+    parse_payload(payload, &ORDER_AMOUNT_FIELDS)
+}
+
+/// Deserializes a raw amendment payload
+///
+/// ### Arguments
+///
+/// * `payload` - The raw JSON payload
+pub fn parse_amendment(payload: Value) -> Result<OrderAmendment, Vec<FieldError>> {
+    parse_payload(payload, &AMENDMENT_AMOUNT_FIELDS)
+}
+
+/// Validates an incoming listing, returning every problem found
+///
+/// ### Arguments
+///
+/// * `listing` - The listing to validate
+pub fn validate_listing(listing: &Listing) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if !is_object_id(&listing._id) {
+        errors.push(FieldError::new(
+            "_id",
+            "Must be a 24 character hex object ID",
+        ));
+    }
+
+    if listing.title.trim().is_empty() {
+        errors.push(FieldError::new("title", "Must not be empty"));
+    }
+
+    if listing.initial_price.is_zero() {
+        errors.push(FieldError::new(
+            "initial_price",
+            "Must be greater than zero",
+        ));
+    }
+
+    if listing.quantity.is_zero() {
+        errors.push(FieldError::new("quantity", "Must be greater than zero"));
+    }
+
+    if listing.decimals > MAX_LISTING_DECIMALS {
+        errors.push(FieldError::new(
+            "decimals",
+            &format!("Must be at most {MAX_LISTING_DECIMALS}"),
+        ));
+    }
+
+    if !listing.initial_price.is_multiple_of(listing.tick_size) {
+        errors.push(FieldError::new(
+            "initial_price",
+            "Must be a multiple of the tick size",
+        ));
+    }
+
+    if !listing.quantity.is_multiple_of(listing.lot_size) {
+        errors.push(FieldError::new(
+            "quantity",
+            "Must be a multiple of the lot size",
+        ));
+    }
+
+    check_storable(
+        &mut errors,
+        &[
+            ("initial_price", Some(listing.initial_price)),
+            ("quantity", Some(listing.quantity)),
+            ("tick_size", Some(listing.tick_size)),
+            ("lot_size", Some(listing.lot_size)),
+            ("min_quantity", Some(listing.min_quantity)),
+            ("min_notional", Some(listing.min_notional)),
+        ],
+    );
+
+    errors
+}
+
+/// Validates an incoming order, returning every problem found. Whether its listing
+/// exists, and whether it meets the listing's trading rules, is checked separately
+///
+/// ### Arguments
+///
+/// * `order` - The order to validate
+pub fn validate_order(order: &Order) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let priced =
+        order.is_barter() || matches!(order.order_type, OrderType::Limit | OrderType::StopLimit);
+
+    if !is_object_id(&order.listing_id) {
+        errors.push(FieldError::new(
+            "listing_id",
+            "Must be a 24 character hex object ID",
+        ));
+    }
+
+    if order.quantity.is_zero() {
+        errors.push(FieldError::new("quantity", "Must be greater than zero"));
+    }
+
+    if priced && order.price.is_zero() {
+        errors.push(FieldError::new("price", "Must be greater than zero"));
+    }
+
+    if order.is_bid && order.druid.is_some() {
+        errors.push(FieldError::new(
+            "druid",
+            "Must not be set on a bid, as DRUIDs are assigned at match time",
+        ));
+    }
+
+    if order.is_stop() && order.trigger_price.is_none() {
+        errors.push(FieldError::new("trigger_price", "Required for stop orders"));
+    }
+
+    if !order.is_stop() && order.trigger_price.is_some() {
+        errors.push(FieldError::new(
+            "trigger_price",
+            "Only allowed on stop orders",
+        ));
+    }
+
+    if let Some(display) = order.display_quantity {
+        if display.is_zero() || display > order.quantity {
+            errors.push(FieldError::new(
+                "display_quantity",
+                "Must be greater than zero and at most the quantity",
+            ));
+        }
+    }
+
+    if !order.reserve_quantity.is_zero() {
+        errors.push(FieldError::new(
+            "reserve_quantity",
+            "Set by the server, use display_quantity instead",
+        ));
+    }
+
+    if order.is_barter() && !is_object_id(order.desired_listing_id.as_deref().unwrap_or_default()) {
+        errors.push(FieldError::new(
+            "desired_listing_id",
+            "Must be a 24 character hex object ID",
+        ));
+    }
+
+    if order.desired_listing_id.as_deref() == Some(order.listing_id.as_str()) {
+        errors.push(FieldError::new(
+            "desired_listing_id",
+            "Must be a different listing to the one being offered",
+        ));
+    }
+
+    if order
+        .expires_at
+        .map(|expiry| expiry <= Utc::now().timestamp())
+        .unwrap_or(false)
+    {
+        errors.push(FieldError::new("expires_at", "Must be in the future"));
+    }
+
+    check_storable(
+        &mut errors,
+        &[
+            ("price", Some(order.price)),
+            ("quantity", Some(order.quantity)),
+            ("display_quantity", order.display_quantity),
+            ("trigger_price", order.trigger_price),
+        ],
+    );
+
+    errors
+}
+
+/// Validates an amendment to a resting order, returning every problem found. The
+/// amended order must still meet its listing's trading rules. The amended quantity is
+/// the order's open quantity, so quantity already reserved by its pending trades isn't
+/// affected. A zero quantity cancels the order, so isn't checked against the listing
+///
+/// ### Arguments
+///
+/// * `listing` - The listing the order belongs to
+/// * `order` - The resting order to be amended
+/// * `amendment` - The new price and/or quantity for the order
+pub fn validate_amendment(
+    listing: &Listing,
+    order: &Order,
+    amendment: &OrderAmendment,
+) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let amended = Order {
+        price: amendment.price.unwrap_or(order.price),
+        quantity: amendment.quantity.unwrap_or(order.total_quantity()),
+        reserve_quantity: Amount::ZERO,
+        ..order.clone()
+    };
+
+    if amendment
+        .price
+        .map(|price| price.is_zero())
+        .unwrap_or(false)
+    {
+        errors.push(FieldError::new("price", "Must be greater than zero"));
+    }
+
+    check_storable(
+        &mut errors,
+        &[("price", amendment.price), ("quantity", amendment.quantity)],
+    );
+
+    if errors.is_empty() && !amended.quantity.is_zero() {
+        errors.extend(listing.check_order(&amended).err().map(FieldError::from));
+    }
+
+    errors
+}
+
+/// Prepares a validated order for the orderbook, assigning the fields the server is
+/// responsible for rather than trusting the client
+///
+/// ### Arguments
+///
+/// * `order` - The incoming order
+pub fn prepare_order(order: Order) -> Order {
+    Order {
+        // We can use the same function to get a base order ID as for a DRUID
+        id: construct_druid(),
+        created_at: Utc::now().to_string(),
+        reserved_quantity: Amount::ZERO,
+        sequence: 0,
+        ..order
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_report_every_invalid_order_field() {
+        //
+        // Arrange
+        //
+        let order = Order {
+            id: String::from("client-chosen"),
+            listing_id: String::from("not-an-id"),
+            is_bid: true,
+            druid: Some(String::from("abc")),
+            order_type: OrderType::Stop,
+            ..Default::default()
+        };
+
+        //
+        // Act
+        //
+        let errors = validate_order(&order);
+        let prepared = prepare_order(order);
+
+        //
+        // Assert
+        //
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec!["listing_id", "quantity", "druid", "trigger_price"]
+        );
+        assert_ne!(prepared.id, String::from("client-chosen"));
+        assert!(!prepared.created_at.is_empty());
+    }
+
+    #[test]
+    fn should_reject_barter_for_its_own_listing() {
+        //
+        // Arrange
+        //
+        let listing_id = String::from("a8f163782fb07c69f511248e");
+        let order = Order {
+            listing_id: listing_id.clone(),
+            desired_listing_id: Some(listing_id),
+            price: Amount::new(1),
+            quantity: Amount::new(1),
+            ..Default::default()
+        };
+
+        //
+        // Act
+        //
+        let errors = validate_order(&order);
+
+        //
+        // Assert
+        //
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["desired_listing_id"]);
+    }
+
+    #[test]
+    fn should_hold_amendments_to_listing_rules() {
+        //
+        // Arrange
+        //
+        let listing = Listing {
+            tick_size: Amount::new(5),
+            lot_size: Amount::new(10),
+            ..Default::default()
+        };
+        let order = Order {
+            price: Amount::new(50),
+            quantity: Amount::new(20),
+            ..Default::default()
+        };
+        let off_rules = OrderAmendment {
+            price: Some(Amount::new(52)),
+            quantity: Some(Amount::new(25)),
+        };
+        let zero_price = OrderAmendment {
+            price: Some(Amount::ZERO),
+            quantity: None,
+        };
+        let valid = OrderAmendment {
+            price: Some(Amount::new(55)),
+            quantity: Some(Amount::new(30)),
+        };
+
+        //
+        // Act
+        //
+        let off_rules = validate_amendment(&listing, &order, &off_rules);
+        let zero_price = validate_amendment(&listing, &order, &zero_price);
+        let valid = validate_amendment(&listing, &order, &valid);
+
+        //
+        // Assert
+        //
+        assert_eq!(off_rules[0].field, "quantity");
+        assert_eq!(zero_price[0].field, "price");
+        assert!(valid.is_empty());
+    }
+
+    #[test]
+    fn should_amend_partly_matched_order() {
+        //
+        // Arrange
+        //
+        let listing = Listing::default();
+        let order = Order {
+            price: Amount::new(50),
+            quantity: Amount::new(2),
+            reserved_quantity: Amount::new(8),
+            ..Default::default()
+        };
+        let reprice = OrderAmendment {
+            price: Some(Amount::new(55)),
+            quantity: None,
+        };
+        let shrink = OrderAmendment {
+            price: None,
+            quantity: Some(Amount::new(1)),
+        };
+
+        //
+        // Act
+        //
+        let reprice = validate_amendment(&listing, &order, &reprice);
+        let shrink = validate_amendment(&listing, &order, &shrink);
+
+        //
+        // Assert
+        //
+        assert!(reprice.is_empty());
+        assert!(shrink.is_empty());
+    }
+
+    #[test]
+    fn should_reject_amounts_too_large_to_store() {
+        //
+        // Arrange
+        //
+        let order = Order {
+            listing_id: String::from("a8f163782fb07c69f511248e"),
+            price: Amount::new(1),
+            quantity: Amount::new(i64::MAX as u64 + 1),
+            ..Default::default()
+        };
+        let listing = Listing {
+            _id: String::from("a8f163782fb07c69f511248e"),
+            title: String::from("Listing"),
+            initial_price: Amount::new(u64::MAX),
+            quantity: Amount::MAX,
+            ..Default::default()
+        };
+
+        //
+        // Act
+        //
+        let order_errors = validate_order(&order);
+        let listing_errors = validate_listing(&listing);
+
+        //
+        // Assert
+        //
+        let fields: Vec<&str> = order_errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["quantity"]);
+        let fields: Vec<&str> = listing_errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["initial_price"]);
+    }
+}
@@ -1,3 +1,6 @@
+// Section headers are doc-style banners, not item docs
+#![allow(clippy::empty_line_after_doc_comments)]
+
 /// ==== DRUID ==== ///
 
 pub const DRUID_CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
@@ -11,3 +14,49 @@ pub const DRUID_LENGTH: usize = 16;
 pub const MARKET_DB_NAME: &str = "market";
 pub const MARKET_COLL_NAME: &str = "listings";
 pub const MARKET_COLL_NAME_ORDERS: &str = "orders";
+pub const MARKET_COLL_NAME_JOURNAL: &str = "journal";
+pub const MARKET_COLL_NAME_SNAPSHOTS: &str = "snapshots";
+
+/// ==== LISTINGS ==== ///
+
+/// The most decimal places a listing's base unit may represent
+pub const MAX_LISTING_DECIMALS: u32 = 18;
+
+/// ==== SETTLEMENT ==== ///
+
+/// Seconds a matched trade may wait for settlement before it expires
+pub const PENDING_TRADE_TIMEOUT: i64 = 600;
+
+/// Settled trades kept in an orderbook. Older ones are folded into its fee totals
+pub const MAX_SETTLED_TRADES: usize = 100;
+
+/// ==== FEES ==== ///
+
+/// The highest fee rate, in basis points, that may be charged on a trade
+pub const MAX_FEE_BPS: u32 = 10_000;
+
+/// ==== BARTER ==== ///
+
+/// The most barter orders the ring solver will link into a single swap
+pub const MAX_RING_LENGTH: usize = 4;
+
+/// ==== CONCURRENCY ==== ///
+
+/// The most times an orderbook update is retried after losing a race with another writer
+pub const MAX_ORDER_BOOK_RETRIES: usize = 8;
+
+/// ==== ENGINE ==== ///
+
+/// The most commands that may queue up for a single listing's order book actor
+pub const ENGINE_QUEUE_CAPACITY: usize = 1024;
+
+/// Milliseconds to wait before retrying a failed write of an order book or its journal
+pub const ENGINE_PERSIST_RETRY_MS: u64 = 500;
+
+/// ==== JOURNAL ==== ///
+
+/// Journal entries written since a listing's last snapshot before a new one is taken
+pub const SNAPSHOT_EVERY_ENTRIES: u64 = 1000;
+
+/// Snapshots kept per listing. Journal entries older than the oldest are compacted away
+pub const RETAINED_SNAPSHOTS: usize = 2;
@@ -0,0 +1,213 @@
+use crate::constants::MAX_ORDER_BOOK_RETRIES;
+use crate::db::errors::MarketError;
+use crate::db::interfaces::MongoDbOrderBook;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::Collection;
+use std::future::Future;
+
+/// Constructs a filter that only matches an orderbook still at the given version.
+/// Orderbooks stored before versioning have no version field, and count as version 0
+///
+/// ### Arguments
+///
+/// * `id` - The ID of the orderbook
+/// * `version` - The version the orderbook was read at
+pub fn construct_version_filter(id: ObjectId, version: u64) -> Document {
+    if version == 0 {
+        doc! { "_id": id, "version": { "$in": [0_i64, Bson::Null] } }
+    } else {
+        doc! { "_id": id, "version": version as i64 }
+    }
+}
+
+/// Constructs the filter and replacement that store an updated orderbook as its next
+/// version. The filter only matches the orderbook at the version it was read at
+///
+/// ### Arguments
+///
+/// * `ob` - The updated orderbook, still carrying the version it was read at
+pub fn construct_versioned_replacement(mut ob: MongoDbOrderBook) -> (Document, MongoDbOrderBook) {
+    let filter = construct_version_filter(ob._id, ob.version);
+    ob.version += 1;
+
+    (filter, ob)
+}
+
+/// Replaces a stored orderbook with a new version, but only if nobody else has written
+/// it since it was read. Returns whether the orderbook was replaced
+///
+/// ### Arguments
+///
+/// * `collection` - The collection holding the orderbook
+/// * `ob` - The updated orderbook, still carrying the version it was read at
+pub async fn replace_if_unchanged(
+    collection: &Collection<MongoDbOrderBook>,
+    ob: MongoDbOrderBook,
+) -> Result<bool, MarketError> {
+    let (filter, ob) = construct_versioned_replacement(ob);
+
+    match collection.replace_one(filter, ob, None).await {
+        Ok(result) => Ok(result.matched_count == 1),
+        Err(_) => Err(MarketError::Storage(
+            "Couldn't update orderbook in DB".to_string(),
+        )),
+    }
+}
+
+/// Runs a read-modify-write attempt against an orderbook until it succeeds. An attempt
+/// returns `Ok(None)` when the orderbook changed between being read and written, in which
+/// case it is run again from a fresh read
+///
+/// ### Arguments
+///
+/// * `attempt` - Reads, updates and conditionally writes the orderbook
+pub async fn retry_on_conflict<T, F, Fut>(mut attempt: F) -> Result<T, MarketError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<T>, MarketError>>,
+{
+    for _ in 0..MAX_ORDER_BOOK_RETRIES {
+        if let Some(value) = attempt().await? {
+            return Ok(value);
+        }
+    }
+
+    Err(MarketError::Conflict(
+        "Orderbook is being updated too often, please try again".to_string(),
+    ))
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::amount::Amount;
+    use crate::market::interfaces::{Order, OrderBook};
+    use futures::future::join_all;
+    use futures::lock::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn create_order(id: &str, quantity: u64, is_bid: bool) -> Order {
+        Order {
+            id: id.to_string(),
+            listing_id: String::from("1"),
+            price: Amount::new(10),
+            quantity: Amount::new(quantity),
+            is_bid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_only_replace_orderbook_at_version_read() {
+        //
+        // Arrange
+        //
+        let id = ObjectId::new();
+        let unversioned = MongoDbOrderBook {
+            _id: id,
+            order_book: OrderBook::new(),
+            version: 0,
+        };
+        let versioned = MongoDbOrderBook {
+            version: 3,
+            ..unversioned.clone()
+        };
+
+        //
+        // Act
+        //
+        let (first_filter, first) = construct_versioned_replacement(unversioned.clone());
+        let (stale_filter, _) = construct_versioned_replacement(unversioned);
+        let (next_filter, next) = construct_versioned_replacement(first.clone());
+        let (filter, replacement) = construct_versioned_replacement(versioned);
+
+        //
+        // Assert
+        //
+        assert_eq!(
+            first_filter,
+            doc! { "_id": id, "version": { "$in": [0_i64, Bson::Null] } }
+        );
+        assert_eq!(first.version, 1);
+        assert_ne!(stale_filter, next_filter);
+        assert_eq!(next_filter, doc! { "_id": id, "version": 1_i64 });
+        assert_eq!(next.version, 2);
+        assert_eq!(filter, doc! { "_id": id, "version": 3_i64 });
+        assert_eq!(replacement.version, 4);
+    }
+
+    #[test]
+    fn should_not_lose_fills_from_concurrent_orders() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        order_book
+            .add_order(&mut create_order("ask", 10, false))
+            .unwrap();
+
+        // Stands in for the orders collection, only accepting writes whose filter
+        // matches the version stored
+        let stored = Arc::new(Mutex::new(MongoDbOrderBook {
+            _id: ObjectId::new(),
+            order_book,
+            version: 0,
+        }));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let rejected = Arc::new(AtomicUsize::new(0));
+
+        let place_bid = |id: String| {
+            let (stored, attempts, rejected) = (stored.clone(), attempts.clone(), rejected.clone());
+            retry_on_conflict(move || {
+                let (stored, attempts, rejected) =
+                    (stored.clone(), attempts.clone(), rejected.clone());
+                let id = id.clone();
+                async move {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    let mut ob = stored.lock().await.clone();
+
+                    // Lets the other bids read the same version before this one writes
+                    tokio::task::yield_now().await;
+                    let outcome = ob.order_book.add_order(&mut create_order(&id, 2, true));
+                    let (filter, ob) = construct_versioned_replacement(ob);
+
+                    let mut current = stored.lock().await;
+                    if filter != construct_version_filter(current._id, current.version) {
+                        rejected.fetch_add(1, Ordering::SeqCst);
+                        return Ok(None);
+                    }
+                    *current = ob;
+                    Ok(Some(outcome.unwrap()))
+                }
+            })
+        };
+
+        //
+        // Act
+        //
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let outcomes = runtime.block_on(join_all((0..5).map(|i| place_bid(format!("bid{i}")))));
+        let stored = runtime.block_on(stored.lock()).clone();
+
+        //
+        // Assert
+        //
+        assert!(outcomes
+            .iter()
+            .all(|o| o.as_ref().unwrap().filled == Amount::new(2)));
+        assert!(rejected.load(Ordering::SeqCst) > 0);
+        assert_eq!(
+            attempts.load(Ordering::SeqCst),
+            5 + rejected.load(Ordering::SeqCst)
+        );
+        assert_eq!(stored.version, 5);
+        assert_eq!(stored.order_book.pending_trades.len(), 5);
+        assert!(stored.order_book.best_ask().is_none());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::market::interfaces::OrderBookError;
+use crate::market::journal::ReplayError;
+use std::fmt;
+use valence_core::api::errors::ApiErrorType;
+use warp::hyper::StatusCode;
+
+/// Errors returned by a `MarketDatabase`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarketError {
+    /// The requested listing, orderbook, order or trade doesn't exist
+    NotFound(String),
+    /// An ID couldn't be parsed
+    InvalidId(String),
+    /// The operation conflicts with the current state of the orderbook
+    Conflict(String),
+    /// The underlying store failed to read or write
+    Storage(String),
+    /// A stored document couldn't be serialized or deserialized
+    Serialization(String),
+}
+
+impl MarketError {
+    /// Returns the HTTP status code this error is reported with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MarketError::NotFound(_) => StatusCode::NOT_FOUND,
+            MarketError::InvalidId(_) => StatusCode::BAD_REQUEST,
+            MarketError::Conflict(_) => StatusCode::CONFLICT,
+            MarketError::Storage(_) | MarketError::Serialization(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Returns the API error type this error is reported with
+    pub fn api_error_type(&self) -> ApiErrorType {
+        match self {
+            MarketError::Serialization(_) => ApiErrorType::DataSerializationFailed,
+            _ => ApiErrorType::Generic(self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for MarketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MarketError::NotFound(message)
+            | MarketError::Conflict(message)
+            | MarketError::Storage(message)
+            | MarketError::Serialization(message) => write!(f, "{message}"),
+            MarketError::InvalidId(id) => write!(f, "Invalid ID: {id}"),
+        }
+    }
+}
+
+impl From<OrderBookError> for MarketError {
+    fn from(error: OrderBookError) -> Self {
+        match error {
+            OrderBookError::OrderNotFound(_) | OrderBookError::TradeNotFound(_) => {
+                MarketError::NotFound(error.to_string())
+            }
+            OrderBookError::PostOnlyWouldCross(_) | OrderBookError::InvalidTradeTransition(..) => {
+                MarketError::Conflict(error.to_string())
+            }
+        }
+    }
+}
+
+impl From<ReplayError> for MarketError {
+    fn from(error: ReplayError) -> Self {
+        MarketError::Storage(error.to_string())
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::interfaces::TradeStatus;
+
+    #[test]
+    fn should_map_order_book_errors_to_statuses() {
+        //
+        // Arrange
+        //
+        let not_found = MarketError::from(OrderBookError::OrderNotFound(String::from("1")));
+        let conflict = MarketError::from(OrderBookError::InvalidTradeTransition(
+            TradeStatus::Confirmed,
+            TradeStatus::Failed,
+        ));
+
+        //
+        // Act
+        //
+        let statuses = [not_found.status(), conflict.status()];
+
+        //
+        // Assert
+        //
+        assert_eq!(statuses, [StatusCode::NOT_FOUND, StatusCode::CONFLICT]);
+        assert_eq!(
+            MarketError::InvalidId(String::from("x")).status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            MarketError::Storage(String::from("down")).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::db::errors::MarketError;
+use crate::db::traits::MarketDatabase;
+use chrono::prelude::Utc;
+use futures::lock::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Purges all orders that have expired as of now from every stored orderbook,
+/// returning how many were removed
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+pub async fn sweep_expired_orders<D: MarketDatabase + Send>(
+    db: &Arc<Mutex<D>>,
+) -> Result<usize, MarketError> {
+    let db_lock = db.lock().await;
+    db_lock.purge_expired_orders(Utc::now().timestamp()).await
+}
+
+/// Expires all pending trades past their settlement deadline as of now in every
+/// stored orderbook, returning how many were expired
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+pub async fn sweep_expired_trades<D: MarketDatabase + Send>(
+    db: &Arc<Mutex<D>>,
+) -> Result<usize, MarketError> {
+    let db_lock = db.lock().await;
+    db_lock.expire_pending_trades(Utc::now().timestamp()).await
+}
+
+/// Spawns a background task that sweeps expired orders and unsettled trades from every
+/// stored orderbook at a fixed interval, logging how many were removed on each sweep
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `period` - The time between sweeps
+pub fn spawn_expiry_sweeper<D: MarketDatabase + Send + Sync + 'static>(
+    db: Arc<Mutex<D>>,
+    period: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            match sweep_expired_orders(&db).await {
+                Ok(0) => (),
+                Ok(removed) => info!("Expiry sweep removed {removed} expired orders"),
+                Err(e) => warn!("Expiry sweep failed: {e}"),
+            }
+
+            match sweep_expired_trades(&db).await {
+                Ok(0) => (),
+                Ok(expired) => info!("Expiry sweep expired {expired} unsettled trades"),
+                Err(e) => warn!("Trade expiry sweep failed: {e}"),
+            }
+        }
+    })
+}
@@ -1,6 +1,10 @@
-use crate::market::interfaces::OrderBook;
+use crate::db::errors::MarketError;
+use crate::market::fees::{FeeRateError, FeeSchedule};
+use crate::market::interfaces::{Listing, OrderBook};
 use futures::lock::Mutex;
 use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{from_document, to_document, Document};
+use mongodb::Client;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use valence_core::db::mongo_db::MongoDbConn;
@@ -10,21 +14,124 @@ use valence_core::db::mongo_db::MongoDbConn;
 pub struct MongoDbOrderBook {
     pub _id: ObjectId,
     pub order_book: OrderBook,
+    /// Incremented on every write, so that a writer can tell if the orderbook
+    /// changed since it was read
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// Converts a listing into the document stored in the listings collection, with its ID
+/// stored as an object ID so that it matches the ID of the listing's orderbook
+///
+/// ### Arguments
+///
+/// * `listing` - The listing to convert
+/// * `id` - The listing's parsed ID
+pub fn construct_listing_document(
+    listing: &Listing,
+    id: ObjectId,
+) -> Result<Document, MarketError> {
+    let mut document = to_document(listing)
+        .map_err(|_| MarketError::Serialization("Couldn't serialize listing".to_string()))?;
+    document.insert("_id", id);
+
+    Ok(document)
+}
+
+/// Reads a listing from a document in the listings collection. IDs stored by earlier
+/// versions as plain strings are read as they are
+///
+/// ### Arguments
+///
+/// * `document` - The stored listing document
+pub fn parse_listing_document(mut document: Document) -> Result<Listing, MarketError> {
+    if let Ok(id) = document.get_object_id("_id") {
+        document.insert("_id", id.to_hex());
+    }
+
+    from_document(document)
+        .map_err(|_| MarketError::Serialization("Couldn't deserialize listing".to_string()))
 }
 
 /// Trait wrapper struct for a MongoDB connection that stores market data
 #[derive(Debug, Clone)]
 pub struct MongoDbConnWithMarket {
     pub inner: Arc<Mutex<MongoDbConn>>,
+    /// The fees charged on trades as orders are matched
+    pub fees: FeeSchedule,
 }
 
 impl MongoDbConnWithMarket {
+    /// Gets the MongoDB client. It is cloned out of the connection's lock, so calls
+    /// don't wait on each other while they talk to the database
+    pub async fn client(&self) -> Client {
+        self.inner.lock().await.client.clone()
+    }
+
     /// Creates a new MongoDbConnWithMarket
     ///
     /// ### Arguments
     ///
     /// * `inner` - The MongoDB connection to wrap
     pub fn new(inner: Arc<Mutex<MongoDbConn>>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            fees: FeeSchedule::default(),
+        }
+    }
+
+    /// Sets the fees charged on trades as orders are matched, failing if any rate in the
+    /// schedule is above the highest allowed rate
+    ///
+    /// ### Arguments
+    ///
+    /// * `fees` - The fee schedule to apply
+    pub fn with_fee_schedule(mut self, fees: FeeSchedule) -> Result<Self, FeeRateError> {
+        fees.validate()?;
+        self.fees = fees;
+        Ok(self)
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::construct_mongodb_object_id;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn should_store_listing_under_its_object_id() {
+        //
+        // Arrange
+        //
+        let id = String::from("a8f163782fb07c69f511248e");
+        let listing = Listing {
+            _id: id.clone(),
+            title: String::from("Asset_test"),
+            ..Default::default()
+        };
+        let legacy = doc! { "_id": id.clone(), "title": "Legacy", "description": "",
+        "initial_price": 1_i64, "quantity": 1_i64 };
+
+        //
+        // Act
+        //
+        let ob_id = construct_mongodb_object_id(id.clone()).unwrap();
+        let document = construct_listing_document(&listing, ob_id).unwrap();
+        let parsed = parse_listing_document(document.clone()).unwrap();
+        let parsed_legacy = parse_listing_document(legacy).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(document.get_object_id("_id").unwrap(), ob_id);
+        assert_eq!(parsed._id, id);
+        assert_eq!(parsed_legacy._id, id);
+        assert_eq!(
+            construct_mongodb_object_id(String::from("garbage")),
+            Err(MarketError::InvalidId(String::from("garbage")))
+        );
     }
 }
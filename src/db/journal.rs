@@ -0,0 +1,166 @@
+use crate::constants::RETAINED_SNAPSHOTS;
+use crate::db::errors::MarketError;
+use crate::db::traits::MarketDatabase;
+use crate::market::interfaces::OrderBook;
+use crate::market::journal::{replay, replay_onto, ReplayError, Snapshot};
+use chrono::prelude::Utc;
+use futures::lock::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Replays a listing's journal onto its latest snapshot, or from the start if it has
+/// none. Returns `None` if the listing has never been journaled
+///
+/// ### Arguments
+///
+/// * `db` - The database to read from
+/// * `listing_id` - The ID of the listing
+async fn replay_journal<D: MarketDatabase + Sync>(
+    db: &D,
+    listing_id: &str,
+) -> Result<Option<OrderBook>, MarketError> {
+    let (order_book, from) = match db.get_snapshots(listing_id.to_string()).await?.pop() {
+        Some(snapshot) => (Some(snapshot.order_book), snapshot.offset),
+        None => (None, 0),
+    };
+    let entries = db.get_journal(listing_id.to_string(), from).await?;
+
+    match order_book {
+        Some(order_book) => Ok(Some(replay_onto(order_book, &entries)?)),
+        None if entries.is_empty() => Ok(None),
+        None => Ok(Some(replay(&entries)?)),
+    }
+}
+
+/// Loads a listing's orderbook from its latest snapshot and the journal entries written
+/// since. Orderbooks that have never been journaled are loaded as stored
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `listing_id` - The ID of the listing whose orderbook should be loaded
+pub async fn load_order_book<D: MarketDatabase + Send + Sync>(
+    db: &Arc<Mutex<D>>,
+    listing_id: String,
+) -> Result<OrderBook, MarketError> {
+    let db_lock = db.lock().await;
+
+    match replay_journal(&*db_lock, &listing_id).await? {
+        Some(order_book) => Ok(order_book),
+        None => db_lock.get_orders_by_id(listing_id).await,
+    }
+}
+
+/// Rebuilds a listing's orderbook from its latest snapshot and journal, storing the
+/// result in place of whatever orderbook was stored. Used to recover an orderbook whose
+/// stored state has been corrupted
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `listing_id` - The ID of the listing whose orderbook should be rebuilt
+pub async fn rebuild_order_book<D: MarketDatabase + Send + Sync>(
+    db: &Arc<Mutex<D>>,
+    listing_id: String,
+) -> Result<OrderBook, MarketError> {
+    let db_lock = db.lock().await;
+    let order_book = match replay_journal(&*db_lock, &listing_id).await? {
+        Some(order_book) => order_book,
+        None => return Err(ReplayError::NotOpened.into()),
+    };
+
+    db_lock
+        .save_order_book(listing_id, order_book.clone())
+        .await?;
+
+    Ok(order_book)
+}
+
+/// Snapshots a listing's orderbook if at least `every` journal entries have been
+/// written since its last snapshot, then compacts away the journal entries and
+/// snapshots older than the ones retained. Returns whether a snapshot was taken
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `listing_id` - The ID of the listing
+/// * `every` - The journal entries written between snapshots
+pub async fn snapshot_order_book<D: MarketDatabase + Send + Sync>(
+    db: &Arc<Mutex<D>>,
+    listing_id: String,
+    every: u64,
+) -> Result<bool, MarketError> {
+    let db_lock = db.lock().await;
+    let mut snapshots = db_lock.get_snapshots(listing_id.clone()).await?;
+    let last_offset = snapshots.last().map_or(0, |s| s.offset);
+
+    // The snapshot is built from the journal rather than the stored orderbook, so it
+    // can never cover entries the journal doesn't hold
+    let order_book = match replay_journal(&*db_lock, &listing_id).await? {
+        Some(order_book) if order_book.journal_offset >= last_offset + every.max(1) => order_book,
+        _ => return Ok(false),
+    };
+
+    let snapshot = Snapshot::new(listing_id.clone(), order_book, Utc::now().timestamp());
+    db_lock.save_snapshot(snapshot.clone()).await?;
+    snapshots.push(snapshot);
+
+    if snapshots.len() > RETAINED_SNAPSHOTS {
+        let oldest = &snapshots[snapshots.len() - RETAINED_SNAPSHOTS];
+        db_lock.compact_journal(listing_id, oldest.offset).await?;
+    }
+
+    Ok(true)
+}
+
+/// Snapshots every listing's orderbook that is due one, returning how many were taken
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `every` - The journal entries written between snapshots
+pub async fn snapshot_order_books<D: MarketDatabase + Send + Sync>(
+    db: &Arc<Mutex<D>>,
+    every: u64,
+) -> Result<usize, MarketError> {
+    let listings = db.lock().await.get_listings().await?;
+    let mut taken = 0;
+
+    for listing in listings {
+        if snapshot_order_book(db, listing._id, every).await? {
+            taken += 1;
+        }
+    }
+
+    Ok(taken)
+}
+
+/// Spawns a background task that snapshots orderbooks and compacts their journals at a
+/// fixed interval, logging how many snapshots were taken on each pass
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `period` - The time between passes
+/// * `every` - The journal entries written between snapshots of a listing
+pub fn spawn_snapshotter<D: MarketDatabase + Send + Sync + 'static>(
+    db: Arc<Mutex<D>>,
+    period: Duration,
+    every: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            match snapshot_order_books(&db, every).await {
+                Ok(0) => (),
+                Ok(taken) => info!("Snapshotter took {taken} orderbook snapshots"),
+                Err(e) => warn!("Snapshotter failed: {e}"),
+            }
+        }
+    })
+}
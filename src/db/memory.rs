@@ -0,0 +1,559 @@
+use crate::db::errors::MarketError;
+use crate::db::traits::MarketDatabase;
+use crate::market::fees::{FeeRateError, FeeReport, FeeSchedule};
+use crate::market::interfaces::{
+    Listing, Order, OrderAmendment, OrderBook, OrderOutcome, PendingTrade, TradeStatus,
+};
+use crate::market::journal::{journal_changes, JournalEntry, OrderEvent, Snapshot};
+use crate::market::rings::match_rings;
+use crate::utils::{construct_initial_orderbook, construct_mongodb_object_id};
+use async_trait::async_trait;
+use chrono::prelude::Utc;
+use futures::lock::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Everything an in-memory market stores, keyed by listing ID
+#[derive(Debug, Default)]
+struct MemoryStore {
+    listings: Vec<Listing>,
+    order_books: BTreeMap<String, OrderBook>,
+    journal: BTreeMap<String, Vec<JournalEntry>>,
+    snapshots: BTreeMap<String, Vec<Snapshot>>,
+}
+
+impl MemoryStore {
+    /// Gets an orderbook by its listing ID
+    ///
+    /// ### Arguments
+    ///
+    /// * `id` - The ID of the listing
+    fn order_book(&self, id: String) -> Result<&OrderBook, MarketError> {
+        let key = construct_mongodb_object_id(id)?.to_hex();
+
+        self.order_books.get(&key).ok_or(MarketError::NotFound(
+            "Couldn't find orderbook with given ID".to_string(),
+        ))
+    }
+
+    /// Applies a change to a copy of an orderbook, storing the copy only if the change
+    /// succeeds. A change that returns the event causing it is journaled
+    ///
+    /// ### Arguments
+    ///
+    /// * `id` - The ID of the listing the orderbook belongs to
+    /// * `apply` - The change to make to the orderbook
+    fn update_order_book<T>(
+        &mut self,
+        id: String,
+        apply: impl FnOnce(&mut OrderBook) -> Result<(T, Option<OrderEvent>), MarketError>,
+    ) -> Result<T, MarketError> {
+        let key = construct_mongodb_object_id(id)?.to_hex();
+        let before = self.order_book(key.clone())?.clone();
+        let mut order_book = before.clone();
+        let (value, cause) = apply(&mut order_book)?;
+
+        if let Some(cause) = cause {
+            let entries = journal_changes(&key, &before, &mut order_book, Some(cause));
+            self.append_journal(entries);
+        }
+        self.order_books.insert(key, order_book);
+
+        Ok(value)
+    }
+
+    /// Applies a change to every orderbook, storing and journaling those it changed.
+    /// Returns how many items the change removed in total
+    ///
+    /// ### Arguments
+    ///
+    /// * `apply` - The change to make, returning how many items it removed and the event
+    ///   that records it
+    fn sweep_order_books(
+        &mut self,
+        apply: impl Fn(&mut OrderBook) -> (usize, OrderEvent),
+    ) -> usize {
+        let mut total = 0;
+        let keys: Vec<String> = self.order_books.keys().cloned().collect();
+
+        for key in keys {
+            let before = self.order_books[&key].clone();
+            let mut order_book = before.clone();
+            let (count, event) = apply(&mut order_book);
+            if count == 0 {
+                continue;
+            }
+
+            let entries = journal_changes(&key, &before, &mut order_book, Some(event));
+            self.append_journal(entries);
+            self.order_books.insert(key, order_book);
+            total += count;
+        }
+
+        total
+    }
+
+    /// Appends entries to the journals of the listings they belong to
+    ///
+    /// ### Arguments
+    ///
+    /// * `entries` - The entries to append
+    fn append_journal(&mut self, entries: Vec<JournalEntry>) {
+        for entry in entries {
+            let journal = self.journal.entry(entry.listing_id.clone()).or_default();
+            journal.push(entry);
+        }
+    }
+}
+
+/// A market database held entirely in memory, for tests and for embedding the market
+/// without MongoDB. It behaves as `MongoDbConnWithMarket` does, and clones share the
+/// same data. Each call holds the whole store, so calls never conflict
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryMarket {
+    inner: Arc<Mutex<MemoryStore>>,
+    /// The fees charged on trades as orders are matched
+    pub fees: FeeSchedule,
+}
+
+impl InMemoryMarket {
+    /// Creates a new, empty InMemoryMarket
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the fees charged on trades as orders are matched, failing if any rate in the
+    /// schedule is above the highest allowed rate
+    ///
+    /// ### Arguments
+    ///
+    /// * `fees` - The fee schedule to apply
+    pub fn with_fee_schedule(mut self, fees: FeeSchedule) -> Result<Self, FeeRateError> {
+        fees.validate()?;
+        self.fees = fees;
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl MarketDatabase for InMemoryMarket {
+    async fn get_listings(&self) -> Result<Vec<Listing>, MarketError> {
+        Ok(self.inner.lock().await.listings.clone())
+    }
+
+    async fn add_listing(&self, mut listing: Listing) -> Result<(), MarketError> {
+        let mut store = self.inner.lock().await;
+        let key = construct_mongodb_object_id(listing._id.clone())?.to_hex();
+
+        if store.order_books.contains_key(&key) {
+            return Err(MarketError::Conflict(
+                "A listing with this ID already exists".to_string(),
+            ));
+        }
+
+        // The listing is stored under the same ID as its orderbook
+        listing._id = key.clone();
+        let mut order_book = construct_initial_orderbook(
+            key.clone(),
+            listing.initial_price,
+            listing.quantity,
+            listing.decimals,
+            None,
+        );
+        order_book.tick_size = listing.tick_size;
+        let opened = OrderEvent::BookOpened {
+            order_book: order_book.clone(),
+        };
+        let entries = journal_changes(&key, &order_book.clone(), &mut order_book, Some(opened));
+
+        store.listings.push(listing);
+        store.order_books.insert(key, order_book);
+        store.append_journal(entries);
+
+        Ok(())
+    }
+
+    async fn get_listing_by_id(&self, id: String) -> Result<Listing, MarketError> {
+        let store = self.inner.lock().await;
+        let key = construct_mongodb_object_id(id)?.to_hex();
+
+        match store.listings.iter().find(|l| l._id == key) {
+            Some(listing) => Ok(listing.clone()),
+            None => Err(MarketError::NotFound(
+                "Couldn't find listing with given ID".to_string(),
+            )),
+        }
+    }
+
+    async fn get_orders_by_id(&self, id: String) -> Result<OrderBook, MarketError> {
+        self.inner.lock().await.order_book(id).cloned()
+    }
+
+    async fn add_order(&self, order: Order) -> Result<OrderOutcome, MarketError> {
+        let mut store = self.inner.lock().await;
+        let rates = self.fees.rates_for(&order.listing_id);
+        let at = Utc::now().timestamp();
+        let accepted = OrderEvent::OrderAccepted {
+            order: order.clone(),
+            at,
+        };
+
+        if !order.is_barter() {
+            return store.update_order_book(order.listing_id.clone(), |order_book| {
+                order_book.fees = rates;
+                let outcome = order_book.add_order_at(&mut order.clone(), at)?;
+                Ok((outcome, Some(accepted)))
+            });
+        }
+
+        // Barter orders are also matched against the orderbook of the listing they want
+        let key = construct_mongodb_object_id(order.listing_id.clone())?.to_hex();
+        let desired_id = order.desired_listing_id.clone().unwrap_or_default();
+        let counter_key = construct_mongodb_object_id(desired_id)?.to_hex();
+        let before = store.order_book(key.clone())?.clone();
+        let original_counter = match counter_key == key {
+            true => None,
+            false => store.order_books.get(&counter_key).cloned(),
+        };
+        let mut counter = original_counter.clone();
+        let mut order_book = before.clone();
+
+        order_book.fees = rates;
+        let outcome = order_book.match_barter(counter.as_mut(), &mut order.clone(), at);
+        let mut entries = journal_changes(&key, &before, &mut order_book, Some(accepted));
+
+        if let Some(mut counter) = counter.filter(|_| !outcome.trades.is_empty()) {
+            if let Some(original) = &original_counter {
+                entries.extend(journal_changes(&counter_key, original, &mut counter, None));
+            }
+            store.order_books.insert(counter_key, counter);
+        }
+        store.order_books.insert(key, order_book);
+        store.append_journal(entries);
+
+        Ok(outcome)
+    }
+
+    async fn cancel_order(
+        &self,
+        listing_id: String,
+        order_id: String,
+    ) -> Result<Order, MarketError> {
+        let mut store = self.inner.lock().await;
+
+        store.update_order_book(listing_id, |order_book| {
+            match order_book.cancel_order(&order_id) {
+                Some(order) => Ok((order, Some(OrderEvent::OrderCancelled { order_id }))),
+                None => Err(MarketError::NotFound(
+                    "Couldn't find order with given ID".to_string(),
+                )),
+            }
+        })
+    }
+
+    async fn amend_order(
+        &self,
+        listing_id: String,
+        order_id: String,
+        amendment: OrderAmendment,
+    ) -> Result<Order, MarketError> {
+        let mut store = self.inner.lock().await;
+        let rates = self.fees.rates_for(&listing_id);
+        let at = Utc::now().timestamp();
+
+        store.update_order_book(listing_id, |order_book| {
+            order_book.fees = rates;
+            let amended = order_book.amend_order_at(&order_id, amendment.clone(), at)?;
+            let event = OrderEvent::OrderAmended {
+                order_id,
+                amendment,
+                at,
+            };
+            Ok((amended, Some(event)))
+        })
+    }
+
+    async fn get_pending_trades_by_id(&self, id: String) -> Result<Vec<PendingTrade>, MarketError> {
+        let store = self.inner.lock().await;
+        Ok(store.order_book(id)?.pending_trades.clone())
+    }
+
+    async fn update_trade_status(
+        &self,
+        druid: String,
+        status: TradeStatus,
+    ) -> Result<PendingTrade, MarketError> {
+        let mut store = self.inner.lock().await;
+        let at = Utc::now().timestamp();
+        let mut trade = None;
+
+        // Barter trades are recorded in the orderbook of every listing they exchange
+        let keys: Vec<String> = store
+            .order_books
+            .iter()
+            .filter(|(_, ob)| ob.pending_trades.iter().any(|t| t.druid == druid))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in keys {
+            let updated = store.update_order_book(key, |order_book| {
+                let updated = order_book.update_trade_status_at(&druid, status, at)?;
+                let druid = druid.clone();
+                Ok((
+                    updated,
+                    Some(OrderEvent::TradeStatusChanged { druid, status, at }),
+                ))
+            })?;
+            trade = Some(updated);
+        }
+
+        trade.ok_or(MarketError::NotFound(
+            "Couldn't find trade with given DRUID".to_string(),
+        ))
+    }
+
+    async fn purge_expired_orders(&self, now: i64) -> Result<usize, MarketError> {
+        let mut store = self.inner.lock().await;
+
+        Ok(store.sweep_order_books(|order_book| {
+            let expired = order_book.purge_expired(now);
+            let event = OrderEvent::OrdersExpired {
+                order_ids: expired.iter().map(|o| o.id.clone()).collect(),
+                at: now,
+            };
+            (expired.len(), event)
+        }))
+    }
+
+    async fn expire_pending_trades(&self, now: i64) -> Result<usize, MarketError> {
+        let mut store = self.inner.lock().await;
+
+        Ok(store.sweep_order_books(|order_book| {
+            let expired = order_book.expire_trades(now);
+            let event = OrderEvent::TradesExpired {
+                druids: expired.iter().map(|t| t.druid.clone()).collect(),
+                at: now,
+            };
+            (expired.len(), event)
+        }))
+    }
+
+    async fn match_barter_rings(&self, now: i64) -> Result<Vec<PendingTrade>, MarketError> {
+        let mut store = self.inner.lock().await;
+        let before = store.order_books.clone();
+        let mut books = before.clone();
+        let trades = match_rings(&mut books, now);
+
+        // Only rewrite orderbooks that took part in a ring
+        for (listing_id, mut order_book) in books {
+            let in_ring = order_book
+                .pending_trades
+                .iter()
+                .any(|t| trades.iter().any(|r| r.druid == t.druid));
+            if !in_ring {
+                continue;
+            }
+
+            let entries = journal_changes(&listing_id, &before[&listing_id], &mut order_book, None);
+            store.append_journal(entries);
+            store.order_books.insert(listing_id, order_book);
+        }
+
+        Ok(trades)
+    }
+
+    async fn get_fee_reports(&self) -> Result<Vec<FeeReport>, MarketError> {
+        let store = self.inner.lock().await;
+
+        Ok(store
+            .order_books
+            .iter()
+            .map(|(id, ob)| FeeReport::from_order_book(id.clone(), ob))
+            .collect())
+    }
+
+    async fn save_order_book(&self, id: String, order_book: OrderBook) -> Result<(), MarketError> {
+        let mut store = self.inner.lock().await;
+        store.update_order_book(id, |stored| {
+            *stored = order_book;
+            Ok(((), None))
+        })
+    }
+
+    async fn append_journal(&self, entries: Vec<JournalEntry>) -> Result<(), MarketError> {
+        self.inner.lock().await.append_journal(entries);
+        Ok(())
+    }
+
+    async fn get_journal(
+        &self,
+        listing_id: String,
+        from: u64,
+    ) -> Result<Vec<JournalEntry>, MarketError> {
+        let store = self.inner.lock().await;
+        let mut entries: Vec<JournalEntry> = match store.journal.get(&listing_id) {
+            Some(journal) => journal
+                .iter()
+                .filter(|e| e.offset >= from)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        entries.sort_by_key(|e| e.offset);
+        Ok(entries)
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot) -> Result<(), MarketError> {
+        let mut store = self.inner.lock().await;
+        let snapshots = store
+            .snapshots
+            .entry(snapshot.listing_id.clone())
+            .or_default();
+
+        snapshots.push(snapshot);
+        Ok(())
+    }
+
+    async fn get_snapshots(&self, listing_id: String) -> Result<Vec<Snapshot>, MarketError> {
+        let store = self.inner.lock().await;
+        let mut snapshots = store
+            .snapshots
+            .get(&listing_id)
+            .cloned()
+            .unwrap_or_default();
+
+        snapshots.sort_by_key(|s| s.offset);
+        Ok(snapshots)
+    }
+
+    async fn compact_journal(&self, listing_id: String, before: u64) -> Result<u64, MarketError> {
+        let mut store = self.inner.lock().await;
+
+        if let Some(snapshots) = store.snapshots.get_mut(&listing_id) {
+            snapshots.retain(|s| s.offset >= before);
+        }
+
+        match store.journal.get_mut(&listing_id) {
+            Some(journal) => {
+                let count = journal.len();
+                journal.retain(|e| e.offset >= before);
+                Ok((count - journal.len()) as u64)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::journal::{rebuild_order_book, snapshot_order_book};
+    use crate::market::amount::Amount;
+    use mongodb::bson::to_document;
+
+    const LISTING_ID: &str = "a8f163782fb07c69f511248e";
+
+    fn create_bid(quantity: u64) -> Order {
+        Order {
+            id: format!("bid{quantity}"),
+            listing_id: LISTING_ID.to_string(),
+            price: Amount::new(10),
+            quantity: Amount::new(quantity),
+            is_bid: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_rebuild_order_book_from_snapshots_and_compacted_journal() {
+        //
+        // Arrange
+        //
+        let db = Arc::new(Mutex::new(InMemoryMarket::new()));
+        let listing = Listing {
+            _id: LISTING_ID.to_string(),
+            title: String::from("Asset_test"),
+            initial_price: Amount::new(10),
+            quantity: Amount::new(100),
+            ..Default::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        //
+        // Act
+        //
+        let (taken, expected, rebuilt, journal, snapshots) = runtime.block_on(async {
+            let db_lock = db.lock().await;
+            db_lock.add_listing(listing).await.unwrap();
+            drop(db_lock);
+
+            let mut taken = Vec::new();
+            for quantity in 1..=4 {
+                db.lock()
+                    .await
+                    .add_order(create_bid(quantity))
+                    .await
+                    .unwrap();
+                taken.push(snapshot_order_book(&db, LISTING_ID.to_string(), 2).await);
+            }
+            db.lock().await.add_order(create_bid(5)).await.unwrap();
+
+            let db_lock = db.lock().await;
+            let expected = db_lock.get_orders_by_id(LISTING_ID.to_string()).await;
+            db_lock
+                .save_order_book(LISTING_ID.to_string(), OrderBook::new())
+                .await
+                .unwrap();
+            drop(db_lock);
+
+            let rebuilt = rebuild_order_book(&db, LISTING_ID.to_string()).await;
+            let db_lock = db.lock().await;
+            let journal = db_lock.get_journal(LISTING_ID.to_string(), 0).await;
+            let snapshots = db_lock.get_snapshots(LISTING_ID.to_string()).await;
+
+            (taken, expected, rebuilt, journal, snapshots)
+        });
+
+        //
+        // Assert
+        //
+        let offsets: Vec<u64> = snapshots.unwrap().iter().map(|s| s.offset).collect();
+        let journal = journal.unwrap();
+
+        assert_eq!(
+            taken.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![true, true, true, true]
+        );
+        assert_eq!(offsets, vec![7, 9]);
+        assert_eq!(journal[0].offset, 7);
+        assert_eq!(journal.last().unwrap().offset, 10);
+        assert_eq!(
+            to_document(&rebuilt.unwrap()).unwrap(),
+            to_document(&expected.unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_refuse_fee_schedule_above_maximum_rate() {
+        //
+        // Arrange
+        //
+        let mut fees = FeeSchedule::default();
+        fees.default.taker_bps = 10_001;
+
+        //
+        // Act
+        //
+        let db = InMemoryMarket::new().with_fee_schedule(fees);
+
+        //
+        // Assert
+        //
+        assert_eq!(db.err(), Some(FeeRateError::TakerAboveMax(10_001)));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::constants::{MARKET_COLL_NAME, MARKET_COLL_NAME_ORDERS, MARKET_DB_NAME};
+use crate::db::errors::MarketError;
+use crate::db::interfaces::MongoDbConnWithMarket;
+use crate::market::amount::Amount;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::Collection;
+
+/// Amount fields stored directly on a listing document
+const LISTING_AMOUNT_FIELDS: [&str; 2] = ["initial_price", "quantity"];
+
+/// Orderbook lists whose entries carry `price` and `quantity` amounts
+const ORDER_BOOK_LISTS: [&str; 3] = ["bids", "asks", "pending_trades"];
+
+/// Converts any legacy floating point values at the given keys into integer base units
+///
+/// ### Arguments
+///
+/// * `document` - The document to convert in place
+/// * `fields` - The keys holding amounts
+/// * `decimals` - The number of decimal places a base unit represents
+pub fn convert_legacy_amounts(
+    document: &mut Document,
+    fields: &[&str],
+    decimals: u32,
+) -> Result<bool, String> {
+    let mut changed = false;
+
+    for field in fields {
+        if let Some(Bson::Double(value)) = document.get(*field) {
+            let units = match Amount::from_f64(*value, decimals) {
+                Some(amount) => i64::try_from(amount.units()).ok(),
+                None => None,
+            };
+            let units = match units {
+                Some(units) => units,
+                None => return Err(format!("Invalid legacy amount for {field}: {value}")),
+            };
+
+            document.insert(*field, Bson::Int64(units));
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Converts the bids, asks and pending trades of a stored orderbook document into
+/// integer base units
+///
+/// ### Arguments
+///
+/// * `order_book` - The `order_book` sub-document of a `MongoDbOrderBook`
+/// * `decimals` - The number of decimal places a base unit represents
+pub fn convert_legacy_order_book(order_book: &mut Document, decimals: u32) -> Result<bool, String> {
+    let mut changed = false;
+
+    for list in ORDER_BOOK_LISTS {
+        if let Ok(entries) = order_book.get_array_mut(list) {
+            for entry in entries.iter_mut() {
+                if let Bson::Document(entry) = entry {
+                    changed |= convert_legacy_amounts(entry, &["price", "quantity"], decimals)?;
+                }
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+impl MongoDbConnWithMarket {
+    /// Migrates listings and orderbooks stored with floating point prices and quantities
+    /// to integer base units, returning the number of documents rewritten. Legacy listings
+    /// have no decimals of their own, so the given `decimals` is applied to all of them
+    ///
+    /// ### Arguments
+    ///
+    /// * `decimals` - The number of decimal places a base unit represents
+    pub async fn migrate_legacy_amounts(&self, decimals: u32) -> Result<usize, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let mut migrated = 0;
+
+        // Listings
+        let listings: Collection<Document> = db.collection(MARKET_COLL_NAME);
+        let mut cursor = match listings.find(doc! {}, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage(
+                    "Couldn't fetch listings from DB".to_string(),
+                ))
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            let mut listing: Document = match cursor.deserialize_current() {
+                Ok(listing) => listing,
+                Err(_) => {
+                    return Err(MarketError::Serialization(
+                        "Couldn't deserialize listing".to_string(),
+                    ))
+                }
+            };
+
+            let changed = convert_legacy_amounts(&mut listing, &LISTING_AMOUNT_FIELDS, decimals)
+                .map_err(MarketError::Serialization)?;
+
+            if changed {
+                listing.insert("decimals", Bson::Int64(decimals as i64));
+                let filter = doc! { "_id": listing.get("_id").cloned().unwrap_or(Bson::Null) };
+
+                if listings.replace_one(filter, listing, None).await.is_err() {
+                    return Err(MarketError::Storage(
+                        "Couldn't update listing in DB".to_string(),
+                    ));
+                }
+                migrated += 1;
+            }
+        }
+
+        // Orderbooks
+        let order_books: Collection<Document> = db.collection(MARKET_COLL_NAME_ORDERS);
+        let mut cursor = match order_books.find(doc! {}, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage(
+                    "Couldn't fetch orderbooks from DB".to_string(),
+                ))
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            let mut ob: Document = match cursor.deserialize_current() {
+                Ok(ob) => ob,
+                Err(_) => {
+                    return Err(MarketError::Serialization(
+                        "Couldn't deserialize orderbook".to_string(),
+                    ))
+                }
+            };
+
+            let changed = match ob.get_document_mut("order_book") {
+                Ok(order_book) => convert_legacy_order_book(order_book, decimals)
+                    .map_err(MarketError::Serialization)?,
+                Err(_) => false,
+            };
+
+            if changed {
+                let filter = doc! { "_id": ob.get("_id").cloned().unwrap_or(Bson::Null) };
+
+                if order_books.replace_one(filter, ob, None).await.is_err() {
+                    return Err(MarketError::Storage(
+                        "Couldn't update orderbook in DB".to_string(),
+                    ));
+                }
+                migrated += 1;
+            }
+        }
+
+        Ok(migrated)
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::interfaces::OrderBook;
+
+    #[test]
+    fn should_convert_legacy_order_book() {
+        //
+        // Arrange
+        //
+        let mut order_book = doc! {
+            "bids": [{ "id": "1", "listing_id": "1", "price": 1.5, "quantity": 0.3,
+                "is_bid": true, "created_at": "", "druid": null, "desired_listing_id": null }],
+            "asks": [],
+            "pending_trades": [{ "bid_id": "1", "ask_id": "2", "price": 0.1, "quantity": 0.2,
+                "created_at": "", "druid": "abc" }],
+        };
+
+        //
+        // Act
+        //
+        let changed = convert_legacy_order_book(&mut order_book, 2).unwrap();
+        let converted: OrderBook = mongodb::bson::from_document(order_book).unwrap();
+        let oversized = convert_legacy_amounts(&mut doc! { "price": 1e19 }, &["price"], 0);
+
+        //
+        // Assert
+        //
+        assert!(changed);
+        assert_eq!(converted.best_bid().unwrap().price, Amount::new(150));
+        assert_eq!(converted.best_bid().unwrap().quantity, Amount::new(30));
+        assert_eq!(converted.pending_trades[0].price, Amount::new(10));
+        assert_eq!(converted.pending_trades[0].quantity, Amount::new(20));
+        assert!(oversized.is_err());
+    }
+}
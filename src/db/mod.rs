@@ -1,2 +1,9 @@
+pub mod concurrency;
+pub mod errors;
+pub mod expiry;
 pub mod interfaces;
+pub mod journal;
+pub mod memory;
+pub mod migrations;
+pub mod rings;
 pub mod traits;
@@ -0,0 +1,49 @@
+use crate::db::errors::MarketError;
+use crate::db::traits::MarketDatabase;
+use chrono::prelude::Utc;
+use futures::lock::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Proposes ring trades for barter orders across every stored orderbook as of now,
+/// returning how many were proposed
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+pub async fn solve_barter_rings<D: MarketDatabase + Send>(
+    db: &Arc<Mutex<D>>,
+) -> Result<usize, MarketError> {
+    let db_lock = db.lock().await;
+    let trades = db_lock.match_barter_rings(Utc::now().timestamp()).await?;
+
+    Ok(trades.len())
+}
+
+/// Spawns a background task that looks for ring trades between barter orders at a
+/// fixed interval, logging how many were proposed on each pass
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to use
+/// * `period` - The time between passes
+pub fn spawn_ring_solver<D: MarketDatabase + Send + Sync + 'static>(
+    db: Arc<Mutex<D>>,
+    period: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            match solve_barter_rings(&db).await {
+                Ok(0) => (),
+                Ok(proposed) => info!("Ring solver proposed {proposed} barter trades"),
+                Err(e) => warn!("Ring solver failed: {e}"),
+            }
+        }
+    })
+}
@@ -1,11 +1,39 @@
-use crate::constants::{ MARKET_COLL_NAME, MARKET_COLL_NAME_ORDERS, MARKET_DB_NAME };
-use crate::db::interfaces::{ MongoDbConnWithMarket, MongoDbOrderBook };
-use crate::market::interfaces::{ Listing, Order, OrderBook, PendingTrade };
+use crate::constants::{
+    MARKET_COLL_NAME,
+    MARKET_COLL_NAME_JOURNAL,
+    MARKET_COLL_NAME_ORDERS,
+    MARKET_COLL_NAME_SNAPSHOTS,
+    MARKET_DB_NAME,
+};
+use crate::db::concurrency::{ construct_versioned_replacement, replace_if_unchanged, retry_on_conflict };
+use crate::db::errors::MarketError;
+use crate::db::interfaces::{
+    construct_listing_document,
+    parse_listing_document,
+    MongoDbConnWithMarket,
+    MongoDbOrderBook,
+};
+use crate::market::interfaces::{
+    Listing,
+    Order,
+    OrderAmendment,
+    OrderBook,
+    OrderOutcome,
+    PendingTrade,
+    TradeStatus,
+};
+use crate::market::fees::FeeReport;
+use crate::market::journal::{ journal_changes, JournalEntry, OrderEvent, Snapshot };
+use crate::market::rings::match_rings;
 use crate::utils::{ construct_mongodb_object_id, construct_initial_orderbook };
 use async_trait::async_trait;
-use mongodb::bson::doc;
-use mongodb::Collection;
-use valence_core::api::errors::{ construct_result_error, ApiError };
+use chrono::prelude::Utc;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{ doc, Document };
+use mongodb::error::{ Error, ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR };
+use mongodb::options::FindOptions;
+use mongodb::{ Client, Collection };
+use std::collections::{ BTreeMap, HashMap };
 
 //====== TRAIT IMPLEMENTATIONS ======//
 
@@ -13,51 +41,157 @@ use valence_core::api::errors::{ construct_result_error, ApiError };
 #[async_trait]
 pub trait MarketDatabase {
     /// Gets all listings from the database
-    async fn get_listings(&self) -> Result<Vec<Listing>, ApiError>;
+    async fn get_listings(&self) -> Result<Vec<Listing>, MarketError>;
 
     /// Adds a listing to the database
     ///
     /// ### Arguments
     ///
     /// * `listing` - The listing to add
-    async fn add_listing(&self, listing: Listing) -> Result<(), ApiError>;
+    async fn add_listing(&self, listing: Listing) -> Result<(), MarketError>;
 
     /// Gets a listing from the database by its ID
     ///
     /// ### Arguments
     ///
     /// * `id` - The ID of the listing to retrieve
-    async fn get_listing_by_id(&self, id: String) -> Result<Listing, ApiError>;
+    async fn get_listing_by_id(&self, id: String) -> Result<Listing, MarketError>;
 
     /// Gets the orderbook for a listing from the database by its ID
     ///
     /// ### Arguments
     ///
     /// * `id` - The ID of the listing to retrieve
-    async fn get_orders_by_id(&self, id: String) -> Result<OrderBook, ApiError>;
+    async fn get_orders_by_id(&self, id: String) -> Result<OrderBook, MarketError>;
 
-    /// Adds an order to the orderbook for a listing
+    /// Adds an order to the orderbook for a listing, returning what was filled,
+    /// left resting and discarded
     ///
     /// ### Arguments
     ///
-    /// * `id` - The ID of the listing to add the order to
     /// * `order` - The order to add
-    async fn add_order(&self, order: Order) -> Result<(), ApiError>;
+    async fn add_order(&self, order: Order) -> Result<OrderOutcome, MarketError>;
+
+    /// Cancels a resting order in the orderbook for a listing, returning the cancelled order
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing the order belongs to
+    /// * `order_id` - The ID of the order to cancel
+    async fn cancel_order(&self, listing_id: String, order_id: String) -> Result<Order, MarketError>;
+
+    /// Amends the price and/or quantity of a resting order, returning the amended order
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing the order belongs to
+    /// * `order_id` - The ID of the order to amend
+    /// * `amendment` - The new price and/or quantity for the order
+    async fn amend_order(
+        &self,
+        listing_id: String,
+        order_id: String,
+        amendment: OrderAmendment
+    ) -> Result<Order, MarketError>;
 
     /// Gets all pending trades for a listing from the database by its ID
     ///
     /// ### Arguments
     ///
     /// * `id` - The ID of the listing to retrieve
-    async fn get_pending_trades_by_id(&self, id: String) -> Result<Vec<PendingTrade>, ApiError>;
+    async fn get_pending_trades_by_id(&self, id: String) -> Result<Vec<PendingTrade>, MarketError>;
+
+    /// Moves a pending trade to a new settlement state by its DRUID, returning the updated trade
+    ///
+    /// ### Arguments
+    ///
+    /// * `druid` - The DRUID of the trade
+    /// * `status` - The state to move the trade to
+    async fn update_trade_status(
+        &self,
+        druid: String,
+        status: TradeStatus
+    ) -> Result<PendingTrade, MarketError>;
+
+    /// Removes expired orders from every stored orderbook, returning how many were removed
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    async fn purge_expired_orders(&self, now: i64) -> Result<usize, MarketError>;
+
+    /// Expires pending trades past their settlement deadline in every stored orderbook,
+    /// returning their quantity to the book and how many trades were expired
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    async fn expire_pending_trades(&self, now: i64) -> Result<usize, MarketError>;
+
+    /// Looks across every stored orderbook for rings of barter orders that can swap with
+    /// one another, holding each ring against a single pending trade. Returns the trades proposed
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    async fn match_barter_rings(&self, now: i64) -> Result<Vec<PendingTrade>, MarketError>;
+
+    /// Gets the fees charged on each listing's trades
+    async fn get_fee_reports(&self) -> Result<Vec<FeeReport>, MarketError>;
+
+    /// Stores the current state of a listing's orderbook, replacing whatever was stored
+    ///
+    /// ### Arguments
+    ///
+    /// * `id` - The ID of the listing the orderbook belongs to
+    /// * `order_book` - The orderbook to store
+    async fn save_order_book(&self, id: String, order_book: OrderBook) -> Result<(), MarketError>;
+
+    /// Appends entries to the journals of the listings they belong to
+    ///
+    /// ### Arguments
+    ///
+    /// * `entries` - The journal entries to append
+    async fn append_journal(&self, entries: Vec<JournalEntry>) -> Result<(), MarketError>;
+
+    /// Gets a listing's journal entries in order, starting from the given offset
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing
+    /// * `from` - The offset of the first entry to get
+    async fn get_journal(&self, listing_id: String, from: u64) -> Result<Vec<JournalEntry>, MarketError>;
+
+    /// Stores a snapshot of a listing's orderbook
+    ///
+    /// ### Arguments
+    ///
+    /// * `snapshot` - The snapshot to store
+    async fn save_snapshot(&self, snapshot: Snapshot) -> Result<(), MarketError>;
+
+    /// Gets a listing's snapshots, oldest first
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing
+    async fn get_snapshots(&self, listing_id: String) -> Result<Vec<Snapshot>, MarketError>;
+
+    /// Removes a listing's journal entries and snapshots from before the given offset,
+    /// returning how many journal entries were removed
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing
+    /// * `before` - The offset of the oldest journal entry to keep
+    async fn compact_journal(&self, listing_id: String, before: u64) -> Result<u64, MarketError>;
 }
 
 #[async_trait]
 impl MarketDatabase for MongoDbConnWithMarket {
-    async fn get_listings(&self) -> Result<Vec<Listing>, ApiError> {
-        let db_lock = self.inner.lock().await;
-        let db = db_lock.client.database(MARKET_DB_NAME);
-        let collection: Collection<Listing> = db.collection(MARKET_COLL_NAME);
+    async fn get_listings(&self) -> Result<Vec<Listing>, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<Document> = db.collection(MARKET_COLL_NAME);
         let mut asset_listings: Vec<Listing> = Vec::new();
 
         // Define a filter (empty document) to retrieve all documents in the collection
@@ -67,86 +201,88 @@ impl MarketDatabase for MongoDbConnWithMarket {
         let mut cursor = match collection.find(filter, None).await {
             Ok(cursor) => cursor,
             Err(_) => {
-                return Err(construct_result_error("Couldn't fetch documents from DB", "listings"));
+                return Err(MarketError::Storage("Couldn't fetch documents from DB".to_string()));
             }
         };
 
         while let Ok(true) = cursor.advance().await {
-            let listing: Listing = match cursor.deserialize_current() {
+            let listing: Document = match cursor.deserialize_current() {
                 Ok(listing) => listing,
                 Err(_) => {
-                    return Err(construct_result_error("Couldn't deserialize listing", "listings"));
+                    return Err(MarketError::Serialization("Couldn't deserialize listing".to_string()));
                 }
             };
 
-            asset_listings.push(listing);
+            asset_listings.push(parse_listing_document(listing)?);
         }
 
         Ok(asset_listings)
     }
 
-    async fn add_listing(&self, listing: Listing) -> Result<(), ApiError> {
-        let db_lock = self.inner.lock().await;
-        let db = db_lock.client.database(MARKET_DB_NAME);
-        let collection = db.collection(MARKET_COLL_NAME);
-        let ob_id = construct_mongodb_object_id(listing._id.clone());
+    async fn add_listing(&self, listing: Listing) -> Result<(), MarketError> {
+        let client = self.client().await;
+        let ob_id = construct_mongodb_object_id(listing._id.clone())?;
 
-        // Insert the BSON document into the collection
-        let _listing_addition = match collection.insert_one(listing.clone(), None).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(construct_result_error("Couldn't insert listing into DB", "listings")),
-        };
-
-        // Create a new orders collection for the listing
-        let orders_collection = db.collection(MARKET_COLL_NAME_ORDERS);
+        // The listing is stored under the same ID as its orderbook
+        let listing_document = construct_listing_document(&listing, ob_id)?;
+        let mut order_book = construct_initial_orderbook(
+            listing._id.clone(),
+            listing.initial_price,
+            listing.quantity,
+            listing.decimals,
+            None
+        );
+        order_book.tick_size = listing.tick_size;
+        let opened = order_book.clone();
+        let entries = journal_changes(
+            &listing._id,
+            &opened,
+            &mut order_book,
+            Some(OrderEvent::BookOpened { order_book: opened.clone() })
+        );
         let new_orderbook = MongoDbOrderBook {
             _id: ob_id,
-            order_book: construct_initial_orderbook(
-                listing._id,
-                listing.initial_price,
-                listing.quantity,
-                None
-            ),
+            order_book,
+            version: 0,
         };
 
-        // Insert the orderbook into the collection
-        match orders_collection.insert_one(new_orderbook, None).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(construct_result_error("Couldn't insert listing into DB", "listings")),
-        }
+        insert_listing_with_order_book(&client, listing_document, new_orderbook).await?;
+
+        let journal = client.database(MARKET_DB_NAME).collection(MARKET_COLL_NAME_JOURNAL);
+        insert_journal_entries(&journal, entries).await
     }
 
-    async fn get_listing_by_id(&self, id: String) -> Result<Listing, ApiError> {
-        let db_lock = self.inner.lock().await;
-        let db = db_lock.client.database(MARKET_DB_NAME);
-        let collection: Collection<Listing> = db.collection(MARKET_COLL_NAME);
-        let filter = doc! { "_id": construct_mongodb_object_id(id) };
+    async fn get_listing_by_id(&self, id: String) -> Result<Listing, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<Document> = db.collection(MARKET_COLL_NAME);
+        let ob_id = construct_mongodb_object_id(id.clone())?;
+
+        // Listings stored by earlier versions have their ID as a plain string
+        let filter = doc! { "_id": { "$in": [ob_id, id] } };
 
         // Retrieve the listing from the database using the filter
         match collection.find_one(filter, None).await {
             Ok(listing) =>
                 match listing {
-                    Some(listing) => Ok(listing),
+                    Some(listing) => parse_listing_document(listing),
                     None => {
                         return Err(
-                            construct_result_error(
-                                "Couldn't find listing with given ID",
-                                "listings"
-                            )
+                            MarketError::NotFound("Couldn't find listing with given ID".to_string())
                         );
                     }
                 }
             Err(_) => {
-                return Err(construct_result_error("Couldn't fetch listing from DB", "listings"));
+                return Err(MarketError::Storage("Couldn't fetch listing from DB".to_string()));
             }
         }
     }
 
-    async fn get_orders_by_id(&self, id: String) -> Result<OrderBook, ApiError> {
-        let db_lock = self.inner.lock().await;
-        let db = db_lock.client.database(MARKET_DB_NAME);
+    async fn get_orders_by_id(&self, id: String) -> Result<OrderBook, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
         let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
-        let filter = doc! { "_id": construct_mongodb_object_id(id) };
+        let filter = doc! { "_id": construct_mongodb_object_id(id)? };
 
         // Retrieve the orderbook from the database using the filter
         match collection.find_one(filter, None).await {
@@ -155,70 +291,127 @@ impl MarketDatabase for MongoDbConnWithMarket {
                     Some(ob) => Ok(ob.order_book),
                     None => {
                         return Err(
-                            construct_result_error(
-                                "Couldn't find orderbook with given ID",
-                                "listings"
-                            )
+                            MarketError::NotFound("Couldn't find orderbook with given ID".to_string())
                         );
                     }
                 }
             Err(_) => {
-                return Err(construct_result_error("Couldn't fetch orderbook from DB", "listings"));
+                return Err(MarketError::Storage("Couldn't fetch orderbook from DB".to_string()));
             }
         }
     }
 
-    async fn add_order(&self, order: Order) -> Result<(), ApiError> {
-        let db_lock = self.inner.lock().await;
-        let db = db_lock.client.database(MARKET_DB_NAME);
+    async fn add_order(&self, order: Order) -> Result<OrderOutcome, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
         let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
-        let filter = doc! { "_id": construct_mongodb_object_id(order.listing_id.clone()) };
+        let ob_id = construct_mongodb_object_id(order.listing_id.clone())?;
+        let rates = self.fees.rates_for(&order.listing_id);
+        let at = Utc::now().timestamp();
 
-        // Retrieve the orderbook from the database using the filter
-        match collection.find_one(filter.clone(), None).await {
-            Ok(ob) =>
-                match ob {
-                    Some(mut ob) => {
-                        let address = order.listing_id.clone();
+        if !order.is_barter() {
+            return update_order_book(&client, ob_id, |ob| {
+                ob.order_book.fees = rates;
+                let outcome = ob.order_book.add_order_at(&mut order.clone(), at)?;
+                Ok((outcome, Some(OrderEvent::OrderAccepted { order: order.clone(), at })))
+            }).await;
+        }
 
-                        ob.order_book.add_order(&mut order.clone());
-                        let new_orderbook = MongoDbOrderBook {
-                            _id: construct_mongodb_object_id(address),
-                            order_book: ob.order_book,
-                        };
+        // Barter orders are also matched against the orderbook of the listing they want
+        let desired_id = order.desired_listing_id.clone().unwrap_or_default();
+        let counter_id = construct_mongodb_object_id(desired_id)?;
+        let client = &client;
+        let collection = &collection;
+        let order = &order;
 
-                        // Insert the BSON document into the collection
-                        match collection.replace_one(filter, new_orderbook, None).await {
-                            Ok(_) => Ok(()),
-                            Err(_) =>
-                                Err(
-                                    construct_result_error(
-                                        "Couldn't insert orderbook into DB",
-                                        "listings"
-                                    )
-                                ),
-                        }
-                    }
-                    None => {
-                        return Err(
-                            construct_result_error(
-                                "Couldn't find orderbook with given ID",
-                                "listings"
-                            )
-                        );
-                    }
+        retry_on_conflict(|| async move {
+            let mut ob = find_order_book(collection, ob_id).await?;
+
+            // An orderbook is never its own counter, or it would be written over itself
+            let counter = match counter_id == ob_id {
+                true => Ok(None),
+                false => collection.find_one(doc! { "_id": counter_id }, None).await,
+            };
+            let mut counter = match counter {
+                Ok(counter) => counter,
+                Err(_) => {
+                    return Err(MarketError::Storage("Couldn't fetch orderbook from DB".to_string()));
+                }
+            };
+            let original = ob.clone();
+            let original_counter = counter.clone();
+
+            ob.order_book.fees = rates;
+            let outcome = ob.order_book.match_barter(
+                counter.as_mut().map(|c| &mut c.order_book),
+                &mut order.clone(),
+                at
+            );
+            let mut entries = journal_changes(
+                &order.listing_id,
+                &original.order_book,
+                &mut ob.order_book,
+                Some(OrderEvent::OrderAccepted { order: order.clone(), at })
+            );
+            let mut updates = vec![(original, ob)];
+
+            // The counter orderbook is only written if the order traded against it
+            if let Some(mut counter) = counter.filter(|_| !outcome.trades.is_empty()) {
+                if let Some(original) = original_counter {
+                    entries.extend(
+                        journal_changes(&counter_id.to_hex(), &original.order_book, &mut counter.order_book, None)
+                    );
+                    updates.push((original, counter));
                 }
-            Err(_) => {
-                return Err(construct_result_error("Couldn't fetch orderbook from DB", "listings"));
             }
-        }
+
+            let written = replace_order_books_if_unchanged(client, updates, entries).await?;
+            Ok(written.then_some(outcome))
+        }).await
+    }
+
+    async fn cancel_order(&self, listing_id: String, order_id: String) -> Result<Order, MarketError> {
+        let client = self.client().await;
+        let ob_id = construct_mongodb_object_id(listing_id)?;
+
+        update_order_book(&client, ob_id, |ob| {
+            match ob.order_book.cancel_order(&order_id) {
+                Some(order) => {
+                    Ok((order, Some(OrderEvent::OrderCancelled { order_id: order_id.clone() })))
+                }
+                None => Err(MarketError::NotFound("Couldn't find order with given ID".to_string())),
+            }
+        }).await
+    }
+
+    async fn amend_order(
+        &self,
+        listing_id: String,
+        order_id: String,
+        amendment: OrderAmendment
+    ) -> Result<Order, MarketError> {
+        let client = self.client().await;
+        let rates = self.fees.rates_for(&listing_id);
+        let ob_id = construct_mongodb_object_id(listing_id)?;
+        let at = Utc::now().timestamp();
+
+        update_order_book(&client, ob_id, |ob| {
+            ob.order_book.fees = rates;
+            let amended = ob.order_book.amend_order_at(&order_id, amendment.clone(), at)?;
+            let event = OrderEvent::OrderAmended {
+                order_id: order_id.clone(),
+                amendment: amendment.clone(),
+                at,
+            };
+            Ok((amended, Some(event)))
+        }).await
     }
 
-    async fn get_pending_trades_by_id(&self, id: String) -> Result<Vec<PendingTrade>, ApiError> {
-        let db_lock = self.inner.lock().await;
-        let db = db_lock.client.database(MARKET_DB_NAME);
+    async fn get_pending_trades_by_id(&self, id: String) -> Result<Vec<PendingTrade>, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
         let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
-        let filter = doc! { "_id": construct_mongodb_object_id(id) };
+        let filter = doc! { "_id": construct_mongodb_object_id(id)? };
 
         // Retrieve the orderbook from the database using the filter
         match collection.find_one(filter, None).await {
@@ -227,16 +420,596 @@ impl MarketDatabase for MongoDbConnWithMarket {
                     Some(ob) => Ok(ob.order_book.pending_trades),
                     None => {
                         return Err(
-                            construct_result_error(
-                                "Couldn't find orderbook with given ID",
-                                "listings"
-                            )
+                            MarketError::NotFound("Couldn't find orderbook with given ID".to_string())
                         );
                     }
                 }
             Err(_) => {
-                return Err(construct_result_error("Couldn't fetch orderbook from DB", "listings"));
+                return Err(MarketError::Storage("Couldn't fetch orderbook from DB".to_string()));
+            }
+        }
+    }
+
+    async fn purge_expired_orders(&self, now: i64) -> Result<usize, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+        let mut removed = 0;
+
+        let mut cursor = match collection.find(doc! {}, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch orderbooks from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            let mut ob: MongoDbOrderBook = match cursor.deserialize_current() {
+                Ok(ob) => ob,
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize orderbook".to_string()));
+                }
+            };
+
+            let original = ob.clone();
+            let expired = ob.order_book.purge_expired(now);
+            if expired.is_empty() {
+                continue;
             }
+
+            let event = OrderEvent::OrdersExpired {
+                order_ids: expired.iter().map(|o| o.id.clone()).collect(),
+                at: now,
+            };
+            let entries = journal_changes(&ob._id.to_hex(), &original.order_book, &mut ob.order_book, Some(event));
+
+            // Only rewrite orderbooks that actually changed. One written since it was read is
+            // left for the next sweep
+            if replace_order_books_if_unchanged(&client, vec![(original, ob)], entries).await? {
+                removed += expired.len();
+            }
+        }
+
+        Ok(removed)
+    }
+
+    async fn update_trade_status(
+        &self,
+        druid: String,
+        status: TradeStatus
+    ) -> Result<PendingTrade, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+        let filter = doc! { "order_book.pending_trades.druid": druid.clone() };
+        let at = Utc::now().timestamp();
+        let mut ob_ids = Vec::new();
+        let mut trade = None;
+
+        // Barter trades are recorded in the orderbook of every listing they exchange
+        let mut cursor = match collection.find(filter, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch orderbook from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            match cursor.deserialize_current() {
+                Ok(ob) => ob_ids.push(ob._id),
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize orderbook".to_string()));
+                }
+            }
+        }
+
+        for ob_id in ob_ids {
+            let updated = update_order_book(&client, ob_id, |ob| {
+                let updated = ob.order_book.update_trade_status_at(&druid, status, at)?;
+                let event = OrderEvent::TradeStatusChanged { druid: druid.clone(), status, at };
+                Ok((updated, Some(event)))
+            }).await?;
+            trade = Some(updated);
+        }
+
+        match trade {
+            Some(trade) => Ok(trade),
+            None => Err(MarketError::NotFound("Couldn't find trade with given DRUID".to_string())),
+        }
+    }
+
+    async fn expire_pending_trades(&self, now: i64) -> Result<usize, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+        let mut expired_count = 0;
+
+        let mut cursor = match collection.find(doc! {}, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch orderbooks from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            let mut ob: MongoDbOrderBook = match cursor.deserialize_current() {
+                Ok(ob) => ob,
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize orderbook".to_string()));
+                }
+            };
+
+            let original = ob.clone();
+            let expired = ob.order_book.expire_trades(now);
+            if expired.is_empty() {
+                continue;
+            }
+
+            let event = OrderEvent::TradesExpired {
+                druids: expired.iter().map(|t| t.druid.clone()).collect(),
+                at: now,
+            };
+            let entries = journal_changes(&ob._id.to_hex(), &original.order_book, &mut ob.order_book, Some(event));
+
+            // Only rewrite orderbooks that actually changed. One written since it was read is
+            // left for the next sweep
+            if replace_order_books_if_unchanged(&client, vec![(original, ob)], entries).await? {
+                expired_count += expired.len();
+            }
+        }
+
+        Ok(expired_count)
+    }
+
+    async fn match_barter_rings(&self, now: i64) -> Result<Vec<PendingTrade>, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+        let mut books = BTreeMap::new();
+        let mut versions = HashMap::new();
+
+        let mut cursor = match collection.find(doc! {}, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch orderbooks from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            let ob: MongoDbOrderBook = match cursor.deserialize_current() {
+                Ok(ob) => ob,
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize orderbook".to_string()));
+                }
+            };
+
+            versions.insert(ob._id.to_hex(), ob.version);
+            books.insert(ob._id.to_hex(), ob.order_book);
+        }
+
+        let before = books.clone();
+        let trades = match_rings(&mut books, now);
+        let mut updates = Vec::new();
+        let mut entries = Vec::new();
+
+        // Only rewrite orderbooks that took part in a ring
+        for (listing_id, mut order_book) in books {
+            let in_ring = order_book.pending_trades
+                .iter()
+                .any(|t| trades.iter().any(|r| r.druid == t.druid));
+            let original = match before.get(&listing_id) {
+                Some(original) if in_ring => original,
+                _ => {
+                    continue;
+                }
+            };
+
+            entries.extend(journal_changes(&listing_id, original, &mut order_book, None));
+            let original = MongoDbOrderBook {
+                _id: construct_mongodb_object_id(listing_id.clone())?,
+                order_book: original.clone(),
+                version: versions.get(&listing_id).copied().unwrap_or_default(),
+            };
+            let ob = MongoDbOrderBook {
+                order_book,
+                ..original.clone()
+            };
+            updates.push((original, ob));
+        }
+
+        if !replace_order_books_if_unchanged(&client, updates, entries).await? {
+            return Err(
+                MarketError::Conflict("Orderbook changed while matching barter rings".to_string())
+            );
+        }
+
+        Ok(trades)
+    }
+
+    async fn get_fee_reports(&self) -> Result<Vec<FeeReport>, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let collection: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+        let mut reports = Vec::new();
+
+        let mut cursor = match collection.find(doc! {}, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch orderbooks from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            let ob: MongoDbOrderBook = match cursor.deserialize_current() {
+                Ok(ob) => ob,
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize orderbook".to_string()));
+                }
+            };
+
+            reports.push(FeeReport::from_order_book(ob._id.to_hex(), &ob.order_book));
+        }
+
+        Ok(reports)
+    }
+
+    async fn save_order_book(&self, id: String, order_book: OrderBook) -> Result<(), MarketError> {
+        let client = self.client().await;
+        let ob_id = construct_mongodb_object_id(id)?;
+
+        update_order_book(&client, ob_id, |ob| {
+            ob.order_book = order_book.clone();
+            Ok(((), None))
+        }).await
+    }
+
+    async fn append_journal(&self, entries: Vec<JournalEntry>) -> Result<(), MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let journal: Collection<JournalEntry> = db.collection(MARKET_COLL_NAME_JOURNAL);
+
+        insert_journal_entries(&journal, entries).await
+    }
+
+    async fn get_journal(&self, listing_id: String, from: u64) -> Result<Vec<JournalEntry>, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let journal: Collection<JournalEntry> = db.collection(MARKET_COLL_NAME_JOURNAL);
+        let filter = doc! { "listing_id": listing_id, "offset": { "$gte": from as i64 } };
+        let options = FindOptions::builder().sort(doc! { "offset": 1 }).build();
+        let mut entries = Vec::new();
+
+        let mut cursor = match journal.find(filter, options).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch journal from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            match cursor.deserialize_current() {
+                Ok(entry) => entries.push(entry),
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize journal entry".to_string()));
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot) -> Result<(), MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let snapshots: Collection<Snapshot> = db.collection(MARKET_COLL_NAME_SNAPSHOTS);
+
+        match snapshots.insert_one(snapshot, None).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(MarketError::Storage("Couldn't write snapshot to DB".to_string())),
+        }
+    }
+
+    async fn get_snapshots(&self, listing_id: String) -> Result<Vec<Snapshot>, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let snapshots: Collection<Snapshot> = db.collection(MARKET_COLL_NAME_SNAPSHOTS);
+        let options = FindOptions::builder().sort(doc! { "offset": 1 }).build();
+        let mut found = Vec::new();
+
+        let mut cursor = match snapshots.find(doc! { "listing_id": listing_id }, options).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                return Err(MarketError::Storage("Couldn't fetch snapshots from DB".to_string()));
+            }
+        };
+
+        while let Ok(true) = cursor.advance().await {
+            match cursor.deserialize_current() {
+                Ok(snapshot) => found.push(snapshot),
+                Err(_) => {
+                    return Err(MarketError::Serialization("Couldn't deserialize snapshot".to_string()));
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    async fn compact_journal(&self, listing_id: String, before: u64) -> Result<u64, MarketError> {
+        let client = self.client().await;
+        let db = client.database(MARKET_DB_NAME);
+        let journal: Collection<JournalEntry> = db.collection(MARKET_COLL_NAME_JOURNAL);
+        let snapshots: Collection<Snapshot> = db.collection(MARKET_COLL_NAME_SNAPSHOTS);
+        let filter = doc! { "listing_id": listing_id, "offset": { "$lt": before as i64 } };
+
+        // Snapshots go first, so none is left pointing at entries that were removed
+        if snapshots.delete_many(filter.clone(), None).await.is_err() {
+            return Err(MarketError::Storage("Couldn't compact snapshots in DB".to_string()));
+        }
+
+        match journal.delete_many(filter, None).await {
+            Ok(result) => Ok(result.deleted_count),
+            Err(_) => Err(MarketError::Storage("Couldn't compact journal in DB".to_string())),
+        }
+    }
+}
+
+//====== HELPERS ======//
+
+/// Fetches an orderbook by its ID
+///
+/// ### Arguments
+///
+/// * `collection` - The collection holding the orderbook
+/// * `id` - The ID of the orderbook
+async fn find_order_book(
+    collection: &Collection<MongoDbOrderBook>,
+    id: ObjectId
+) -> Result<MongoDbOrderBook, MarketError> {
+    match collection.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(ob)) => Ok(ob),
+        Ok(None) => Err(MarketError::NotFound("Couldn't find orderbook with given ID".to_string())),
+        Err(_) => Err(MarketError::Storage("Couldn't fetch orderbook from DB".to_string())),
+    }
+}
+
+/// Fetches an orderbook, applies a change to it and writes it back. If another writer
+/// got there first the change is applied again to a fresh copy, so no update is lost.
+/// A change that returns the event causing it is journaled along with it, so that
+/// either both or neither are stored
+///
+/// ### Arguments
+///
+/// * `client` - The MongoDB client to use
+/// * `id` - The ID of the orderbook
+/// * `apply` - The change to make to the orderbook
+async fn update_order_book<T, F>(
+    client: &Client,
+    id: ObjectId,
+    apply: F
+) -> Result<T, MarketError>
+    where T: Send, F: Fn(&mut MongoDbOrderBook) -> Result<(T, Option<OrderEvent>), MarketError> + Sync
+{
+    let apply = &apply;
+    let collection: Collection<MongoDbOrderBook> = client.database(MARKET_DB_NAME).collection(MARKET_COLL_NAME_ORDERS);
+    let collection = &collection;
+
+    retry_on_conflict(|| async move {
+        let mut ob = find_order_book(collection, id).await?;
+        let original = ob.clone();
+        let (value, cause) = apply(&mut ob)?;
+
+        let written = match cause {
+            Some(cause) => {
+                let entries = journal_changes(&id.to_hex(), &original.order_book, &mut ob.order_book, Some(cause));
+                replace_order_books_if_unchanged(client, vec![(original, ob)], entries).await?
+            }
+            None => replace_if_unchanged(collection, ob).await?,
+        };
+
+        Ok(written.then_some(value))
+    }).await
+}
+
+/// Writes entries to the journal
+///
+/// ### Arguments
+///
+/// * `journal` - The collection holding the journal
+/// * `entries` - The entries to write
+async fn insert_journal_entries(
+    journal: &Collection<JournalEntry>,
+    entries: Vec<JournalEntry>
+) -> Result<(), MarketError> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    match journal.insert_many(entries, None).await {
+        Ok(_) => Ok(()),
+        Err(_) => Err(MarketError::Storage("Couldn't write journal to DB".to_string())),
+    }
+}
+
+/// Writes several updated orderbooks, and the journal entries that led to them, so that
+/// either all or none are stored. Each orderbook is only written if it is still at the
+/// version it was read at, and `Ok(false)` is returned if any has changed. A
+/// multi-document transaction is used where the deployment supports one. Standalone
+/// servers don't, so there the orderbooks are written in turn, and any already written
+/// are put back as they were if a later one has changed or the journal can't be written
+///
+/// ### Arguments
+///
+/// * `client` - The MongoDB client to use
+/// * `updates` - Each orderbook as it was read, along with its update
+/// * `entries` - The journal entries recording the updates
+async fn replace_order_books_if_unchanged(
+    client: &Client,
+    updates: Vec<(MongoDbOrderBook, MongoDbOrderBook)>,
+    entries: Vec<JournalEntry>
+) -> Result<bool, MarketError> {
+    let db = client.database(MARKET_DB_NAME);
+    let orders: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+    let journal: Collection<JournalEntry> = db.collection(MARKET_COLL_NAME_JOURNAL);
+    let storage_error = || MarketError::Storage("Couldn't update orderbooks in DB".to_string());
+
+    let mut session = match client.start_session(None).await {
+        Ok(session) => session,
+        Err(_) => {
+            return Err(storage_error());
+        }
+    };
+
+    match session.start_transaction(None).await {
+        Ok(_) => {
+            for (_, ob) in updates {
+                let (filter, ob) = construct_versioned_replacement(ob);
+
+                // Another transaction writing the same orderbook is a conflict like any other
+                match orders.replace_one_with_session(filter, ob, None, &mut session).await {
+                    Ok(result) if result.matched_count == 1 => (),
+                    Ok(_) => {
+                        let _ = session.abort_transaction().await;
+                        return Ok(false);
+                    }
+                    Err(e) => {
+                        let _ = session.abort_transaction().await;
+                        return match e.contains_label(TRANSIENT_TRANSACTION_ERROR) {
+                            true => Ok(false),
+                            false => Err(storage_error()),
+                        };
+                    }
+                }
+            }
+
+            if !entries.is_empty() && journal.insert_many_with_session(entries, None, &mut session).await.is_err() {
+                let _ = session.abort_transaction().await;
+                return Err(MarketError::Storage("Couldn't write journal to DB".to_string()));
+            }
+
+            match session.commit_transaction().await {
+                Ok(_) => Ok(true),
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) => Ok(false),
+                Err(_) => Err(storage_error()),
+            }
+        }
+        Err(e) if matches!(e.kind.as_ref(), ErrorKind::Transaction { .. }) => {
+            let mut written = Vec::new();
+            let mut result = Ok(true);
+
+            for (original, ob) in updates {
+                let version = ob.version + 1;
+                match replace_if_unchanged(&orders, ob).await {
+                    Ok(true) => written.push((original, version)),
+                    other => {
+                        result = other;
+                        break;
+                    }
+                }
+            }
+
+            if matches!(result, Ok(true)) {
+                result = insert_journal_entries(&journal, entries).await.map(|_| true);
+            }
+
+            if matches!(result, Ok(true)) {
+                return result;
+            }
+
+            // Compensate for the missing transaction by putting back what was written,
+            // as long as nobody has written over it since
+            for (mut original, version) in written.into_iter().rev() {
+                original.version = version;
+                if !replace_if_unchanged(&orders, original).await? {
+                    return Err(MarketError::Storage("Couldn't roll back orderbook in DB".to_string()));
+                }
+            }
+            result
+        }
+        Err(_) => Err(storage_error()),
+    }
+}
+
+/// The server error code for a duplicate key
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// Converts a failed listing insertion into a market error, reporting a duplicate ID
+/// as a conflict
+///
+/// ### Arguments
+///
+/// * `error` - The MongoDB error
+fn construct_listing_insert_error(error: Error) -> MarketError {
+    let code = match error.kind.as_ref() {
+        ErrorKind::Command(e) => Some(e.code),
+        ErrorKind::Write(WriteFailure::WriteError(e)) => Some(e.code),
+        _ => None,
+    };
+
+    match code {
+        Some(DUPLICATE_KEY_CODE) => MarketError::Conflict("A listing with this ID already exists".to_string()),
+        _ => MarketError::Storage("Couldn't insert listing into DB".to_string()),
+    }
+}
+
+/// Inserts a listing and its orderbook so that either both or neither are stored. A
+/// multi-document transaction is used where the deployment supports one. Standalone
+/// servers don't, so there the listing is inserted first and deleted again if its
+/// orderbook can't be inserted
+///
+/// ### Arguments
+///
+/// * `client` - The MongoDB client to use
+/// * `listing` - The listing document
+/// * `order_book` - The listing's initial orderbook
+async fn insert_listing_with_order_book(
+    client: &Client,
+    listing: Document,
+    order_book: MongoDbOrderBook
+) -> Result<(), MarketError> {
+    let db = client.database(MARKET_DB_NAME);
+    let listings: Collection<Document> = db.collection(MARKET_COLL_NAME);
+    let orders: Collection<MongoDbOrderBook> = db.collection(MARKET_COLL_NAME_ORDERS);
+
+    let mut session = match client.start_session(None).await {
+        Ok(session) => session,
+        Err(e) => {
+            return Err(construct_listing_insert_error(e));
+        }
+    };
+
+    match session.start_transaction(None).await {
+        Ok(_) => {
+            let inserted = match listings.insert_one_with_session(listing, None, &mut session).await {
+                Ok(_) => orders.insert_one_with_session(order_book, None, &mut session).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = inserted {
+                let _ = session.abort_transaction().await;
+                return Err(construct_listing_insert_error(e));
+            }
+
+            session.commit_transaction().await.map_err(construct_listing_insert_error)
+        }
+        Err(e) if matches!(e.kind.as_ref(), ErrorKind::Transaction { .. }) => {
+            let listing_id = order_book._id;
+            listings.insert_one(listing, None).await.map_err(construct_listing_insert_error)?;
+
+            // Compensate for the missing transaction by removing the listing again
+            if let Err(e) = orders.insert_one(order_book, None).await {
+                if listings.delete_one(doc! { "_id": listing_id }, None).await.is_err() {
+                    return Err(
+                        MarketError::Storage(
+                            "Couldn't remove listing after its orderbook failed to insert".to_string()
+                        )
+                    );
+                }
+                return Err(construct_listing_insert_error(e));
+            }
+
+            Ok(())
         }
+        Err(e) => Err(construct_listing_insert_error(e)),
     }
 }
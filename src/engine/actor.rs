@@ -0,0 +1,426 @@
+use crate::constants::{ENGINE_PERSIST_RETRY_MS, ENGINE_QUEUE_CAPACITY};
+use crate::db::errors::MarketError;
+use crate::db::traits::MarketDatabase;
+use crate::market::interfaces::{
+    Order, OrderAmendment, OrderBook, OrderOutcome, PendingTrade, TradeStatus,
+};
+use crate::market::journal::{journal_changes, JournalEntry, OrderEvent};
+use chrono::prelude::Utc;
+use futures::lock::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// A lent orderbook given back to its actor, along with the event that changed it
+pub type Returned = (OrderBook, Option<OrderEvent>);
+
+/// A state of an orderbook to be written to the database, along with the journal
+/// entries that led to it
+pub type Change = (OrderBook, Vec<JournalEntry>);
+
+/// A request to the actor that owns a listing's orderbook. Each carries the channel
+/// its result is sent back on
+#[derive(Debug)]
+pub enum BookCommand {
+    /// Matches an order and rests whatever remains of it
+    AddOrder {
+        order: Order,
+        reply: oneshot::Sender<Result<OrderOutcome, MarketError>>,
+    },
+    /// Cancels a resting order, replying with `None` if there is no such order
+    CancelOrder {
+        order_id: String,
+        reply: oneshot::Sender<Option<Order>>,
+    },
+    /// Amends the price and/or quantity of a resting order
+    AmendOrder {
+        order_id: String,
+        amendment: OrderAmendment,
+        reply: oneshot::Sender<Result<Order, MarketError>>,
+    },
+    /// Moves a pending trade to a new settlement state, replying with `None` if the
+    /// orderbook holds no trade with the DRUID
+    UpdateTradeStatus {
+        druid: String,
+        status: TradeStatus,
+        reply: oneshot::Sender<Option<Result<PendingTrade, MarketError>>>,
+    },
+    /// Removes expired orders, replying with how many were removed
+    PurgeExpired {
+        now: i64,
+        reply: oneshot::Sender<usize>,
+    },
+    /// Expires trades past their settlement deadline, replying with how many expired
+    ExpireTrades {
+        now: i64,
+        reply: oneshot::Sender<usize>,
+    },
+    /// Replies with a copy of the orderbook
+    Get { reply: oneshot::Sender<OrderBook> },
+    /// Lends a copy of the orderbook out for matching that spans several listings. The
+    /// actor waits for the copy to be sent back on `returned` and adopts it, carrying on
+    /// with the orderbook it had if it never comes back
+    Lend {
+        reply: oneshot::Sender<OrderBook>,
+        returned: oneshot::Receiver<Returned>,
+    },
+    /// Replaces the orderbook outright, without journaling the change
+    Replace {
+        order_book: OrderBook,
+        reply: oneshot::Sender<()>,
+    },
+}
+
+/// A cloneable handle for sending commands to a listing's orderbook actor
+#[derive(Debug, Clone)]
+pub struct ListingHandle {
+    sender: mpsc::Sender<BookCommand>,
+}
+
+impl ListingHandle {
+    /// Sends a command to the actor and waits for its reply
+    ///
+    /// ### Arguments
+    ///
+    /// * `command` - Builds the command from the channel its reply should be sent on
+    pub async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> BookCommand,
+    ) -> Result<T, MarketError> {
+        let stopped = || MarketError::Storage("Orderbook actor has stopped".to_string());
+        let (reply, response) = oneshot::channel();
+
+        if self.sender.send(command(reply)).await.is_err() {
+            return Err(stopped());
+        }
+
+        response.await.map_err(|_| stopped())
+    }
+
+    /// Borrows a copy of the actor's orderbook, returning it along with the channel it
+    /// must be given back on. The actor handles no other commands until then
+    pub async fn borrow(&self) -> Result<(OrderBook, oneshot::Sender<Returned>), MarketError> {
+        let (give_back, returned) = oneshot::channel();
+        let order_book = self
+            .request(|reply| BookCommand::Lend { reply, returned })
+            .await?;
+
+        Ok((order_book, give_back))
+    }
+}
+
+/// Owns a single listing's orderbook, applying commands to it one at a time
+pub struct ListingActor {
+    listing_id: String,
+    order_book: OrderBook,
+    persist: mpsc::UnboundedSender<Change>,
+}
+
+impl ListingActor {
+    /// Creates an actor for an orderbook, publishing every change to `persist`
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing the orderbook belongs to
+    /// * `order_book` - The orderbook to own
+    /// * `persist` - The channel changes to the orderbook are published on
+    pub fn new(
+        listing_id: String,
+        order_book: OrderBook,
+        persist: mpsc::UnboundedSender<Change>,
+    ) -> Self {
+        Self {
+            listing_id,
+            order_book,
+            persist,
+        }
+    }
+
+    /// Applies a command to the orderbook and replies with its result. Changes are
+    /// published along with the journal entries that record them
+    ///
+    /// ### Arguments
+    ///
+    /// * `command` - The command to apply
+    pub async fn handle(&mut self, command: BookCommand) {
+        let before = self.order_book.clone();
+
+        let (changed, cause) = match command {
+            BookCommand::AddOrder { order, reply } => {
+                let at = Utc::now().timestamp();
+                let outcome = self.order_book.add_order_at(&mut order.clone(), at);
+                let changed = outcome.is_ok();
+                let _ = reply.send(outcome.map_err(MarketError::from));
+                (changed, Some(OrderEvent::OrderAccepted { order, at }))
+            }
+            BookCommand::CancelOrder { order_id, reply } => {
+                let cancelled = self.order_book.cancel_order(&order_id);
+                let changed = cancelled.is_some();
+                let _ = reply.send(cancelled);
+                (changed, Some(OrderEvent::OrderCancelled { order_id }))
+            }
+            BookCommand::AmendOrder {
+                order_id,
+                amendment,
+                reply,
+            } => {
+                let at = Utc::now().timestamp();
+                let amended = self
+                    .order_book
+                    .amend_order_at(&order_id, amendment.clone(), at);
+                let changed = amended.is_ok();
+                let _ = reply.send(amended.map_err(MarketError::from));
+                let event = OrderEvent::OrderAmended {
+                    order_id,
+                    amendment,
+                    at,
+                };
+                (changed, Some(event))
+            }
+            BookCommand::UpdateTradeStatus {
+                druid,
+                status,
+                reply,
+            } => {
+                let at = Utc::now().timestamp();
+                let held = self
+                    .order_book
+                    .pending_trades
+                    .iter()
+                    .any(|t| t.druid == druid);
+                let updated = held.then(|| {
+                    self.order_book
+                        .update_trade_status_at(&druid, status, at)
+                        .map_err(MarketError::from)
+                });
+                let changed = matches!(updated, Some(Ok(_)));
+                let _ = reply.send(updated);
+                let event = OrderEvent::TradeStatusChanged { druid, status, at };
+                (changed, Some(event))
+            }
+            BookCommand::PurgeExpired { now, reply } => {
+                let purged = self.order_book.purge_expired(now);
+                let _ = reply.send(purged.len());
+                let event = OrderEvent::OrdersExpired {
+                    order_ids: purged.iter().map(|o| o.id.clone()).collect(),
+                    at: now,
+                };
+                (!purged.is_empty(), Some(event))
+            }
+            BookCommand::ExpireTrades { now, reply } => {
+                let expired = self.order_book.expire_trades(now);
+                let _ = reply.send(expired.len());
+                let event = OrderEvent::TradesExpired {
+                    druids: expired.iter().map(|t| t.druid.clone()).collect(),
+                    at: now,
+                };
+                (!expired.is_empty(), Some(event))
+            }
+            BookCommand::Get { reply } => {
+                let _ = reply.send(before);
+                return;
+            }
+            BookCommand::Lend { reply, returned } => {
+                if reply.send(before.clone()).is_err() {
+                    return;
+                }
+
+                match returned.await {
+                    Ok((order_book, cause)) => {
+                        self.order_book = order_book;
+                        (true, cause)
+                    }
+                    Err(_) => (false, None),
+                }
+            }
+            BookCommand::Replace { order_book, reply } => {
+                self.order_book = order_book;
+                let _ = self.persist.send((self.order_book.clone(), Vec::new()));
+                let _ = reply.send(());
+                return;
+            }
+        };
+
+        if changed {
+            let entries = journal_changes(&self.listing_id, &before, &mut self.order_book, cause);
+            let _ = self.persist.send((self.order_book.clone(), entries));
+        }
+    }
+}
+
+/// Writes a listing's unwritten journal entries and then its latest orderbook. Whatever
+/// isn't written is left in place to be sent again. A failed journal write may have
+/// stored some of its entries, so those are looked up and dropped rather than being
+/// written twice
+///
+/// ### Arguments
+///
+/// * `db` - The database connection to write with
+/// * `listing_id` - The ID of the listing the orderbook belongs to
+/// * `unwritten` - Journal entries not yet written, in order
+/// * `latest` - The latest state of the orderbook, if not yet written
+async fn write_changes<D: MarketDatabase>(
+    db: &D,
+    listing_id: &str,
+    unwritten: &mut Vec<JournalEntry>,
+    latest: &mut Option<OrderBook>,
+) -> Result<(), MarketError> {
+    if let Some(first) = unwritten.first().map(|e| e.offset) {
+        if let Err(e) = db.append_journal(unwritten.clone()).await {
+            if let Ok(stored) = db.get_journal(listing_id.to_string(), first).await {
+                unwritten.retain(|e| !stored.iter().any(|s| s.offset == e.offset));
+            }
+            return Err(e);
+        }
+        unwritten.clear();
+    }
+
+    // The journal is written first, so a stored orderbook is never ahead of it
+    if let Some(order_book) = latest.take() {
+        if let Err(e) = db
+            .save_order_book(listing_id.to_string(), order_book.clone())
+            .await
+        {
+            *latest = Some(order_book);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawns an actor that owns a listing's orderbook, along with a task that writes the
+/// orderbook and its journal to the database whenever it changes. Writes happen in the
+/// background, and a burst of changes is written once with the latest state, so
+/// matching never waits on the database. Failed writes are retried until they succeed,
+/// as skipping a journal batch would leave a gap that stops the orderbook loading
+///
+/// ### Arguments
+///
+/// * `listing_id` - The ID of the listing the orderbook belongs to
+/// * `order_book` - The orderbook to own
+/// * `db` - The database connection to persist the orderbook with
+pub fn spawn_listing_actor<D: MarketDatabase + Clone + Send + Sync + 'static>(
+    listing_id: String,
+    order_book: OrderBook,
+    db: Arc<Mutex<D>>,
+) -> ListingHandle {
+    let (sender, mut commands) = mpsc::channel(ENGINE_QUEUE_CAPACITY);
+    let (persist, mut changes) = mpsc::unbounded_channel::<Change>();
+    let mut actor = ListingActor::new(listing_id.clone(), order_book, persist);
+
+    tokio::spawn(async move {
+        while let Some(command) = commands.recv().await {
+            actor.handle(command).await;
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some((order_book, mut unwritten)) = changes.recv().await {
+            let mut latest = Some(order_book);
+
+            loop {
+                while let Ok((order_book, more)) = changes.try_recv() {
+                    latest = Some(order_book);
+                    unwritten.extend(more);
+                }
+                // The connection is cloned out, so actors don't wait on each other's writes
+                let db = db.lock().await.clone();
+
+                match write_changes(&db, &listing_id, &mut unwritten, &mut latest).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        warn!("Couldn't persist orderbook for listing {listing_id}, retrying: {e}");
+                        tokio::time::sleep(Duration::from_millis(ENGINE_PERSIST_RETRY_MS)).await;
+                    }
+                }
+            }
+        }
+    });
+
+    ListingHandle { sender }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::amount::Amount;
+    use crate::market::journal::replay;
+
+    fn create_order(id: &str, price: u64, quantity: u64, is_bid: bool) -> Order {
+        Order {
+            id: id.to_string(),
+            listing_id: String::from("1"),
+            price: Amount::new(price),
+            quantity: Amount::new(quantity),
+            is_bid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_apply_commands_and_publish_changes() {
+        //
+        // Arrange
+        //
+        let (persist, mut changes) = mpsc::unbounded_channel();
+        let mut actor = ListingActor::new(String::from("1"), OrderBook::new(), persist);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        //
+        // Act
+        //
+        let (outcome, cancelled, lent) = runtime.block_on(async {
+            let (reply, response) = oneshot::channel();
+            let order = create_order("ask", 10, 5, false);
+            actor.handle(BookCommand::AddOrder { order, reply }).await;
+
+            let (reply, response_bid) = oneshot::channel();
+            let order = create_order("bid", 10, 2, true);
+            actor.handle(BookCommand::AddOrder { order, reply }).await;
+
+            let (reply, cancel_response) = oneshot::channel();
+            let order_id = String::from("missing");
+            actor
+                .handle(BookCommand::CancelOrder { order_id, reply })
+                .await;
+
+            let (reply, lent) = oneshot::channel();
+            let (give_back, returned) = oneshot::channel();
+            drop(give_back);
+            actor.handle(BookCommand::Lend { reply, returned }).await;
+
+            assert!(response.await.unwrap().is_ok());
+            (
+                response_bid.await.unwrap().unwrap(),
+                cancel_response.await.unwrap(),
+                lent.await.unwrap(),
+            )
+        });
+
+        let mut published = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            published.push(change);
+        }
+        let entries: Vec<JournalEntry> = published.iter().flat_map(|(_, e)| e.clone()).collect();
+        let (latest, _) = published.last().unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(outcome.filled, Amount::new(2));
+        assert!(cancelled.is_none());
+        assert_eq!(lent.pending_trades.len(), 1);
+        assert_eq!(published.len(), 2);
+        assert_eq!(latest.best_ask().unwrap().quantity, Amount::new(3));
+        assert_eq!(
+            replay(&entries).unwrap().pending_trades[0].druid,
+            latest.pending_trades[0].druid
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use crate::db::errors::MarketError;
+use crate::db::journal::load_order_book;
+use crate::db::traits::MarketDatabase;
+use crate::engine::actor::{spawn_listing_actor, BookCommand, ListingHandle};
+use crate::market::fees::{FeeReport, FeeSchedule};
+use crate::market::interfaces::{
+    Listing, Order, OrderAmendment, OrderBook, OrderOutcome, PendingTrade, TradeStatus,
+};
+use crate::market::journal::{JournalEntry, OrderEvent, Snapshot};
+use crate::market::rings::match_rings;
+use crate::utils::construct_mongodb_object_id;
+use async_trait::async_trait;
+use chrono::prelude::Utc;
+use futures::lock::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// An in-memory matching engine that runs one actor per listing, each owning that
+/// listing's orderbook. Orders for different listings are matched in parallel, and
+/// changes are written to the database in the background. All orderbooks are loaded
+/// when the engine starts, and from then on the engine must be the only writer to them
+#[derive(Debug)]
+pub struct MatchingEngine<D> {
+    db: Arc<Mutex<D>>,
+    books: Arc<Mutex<BTreeMap<String, ListingHandle>>>,
+    /// Every listing the engine runs, so orders can be checked against them without
+    /// going to the database
+    listings: Arc<Mutex<BTreeMap<String, Listing>>>,
+    /// Held while matching spans several orderbooks, so two such matches can't wait
+    /// on each other's books
+    barter_lock: Arc<Mutex<()>>,
+    fees: FeeSchedule,
+}
+
+impl<D> Clone for MatchingEngine<D> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            books: self.books.clone(),
+            listings: self.listings.clone(),
+            barter_lock: self.barter_lock.clone(),
+            fees: self.fees.clone(),
+        }
+    }
+}
+
+impl<D: MarketDatabase + Clone + Send + Sync + 'static> MatchingEngine<D> {
+    /// Starts an engine over every listing stored in the database
+    ///
+    /// ### Arguments
+    ///
+    /// * `db` - The database connection to load and persist orderbooks with
+    /// * `fees` - The fees charged on trades as orders are matched
+    pub async fn start(db: Arc<Mutex<D>>, fees: FeeSchedule) -> Result<Self, MarketError> {
+        let engine = Self {
+            db,
+            books: Arc::new(Mutex::new(BTreeMap::new())),
+            listings: Arc::new(Mutex::new(BTreeMap::new())),
+            barter_lock: Arc::new(Mutex::new(())),
+            fees,
+        };
+
+        let listings = engine.db.lock().await.get_listings().await?;
+        for listing in listings {
+            engine.load(listing).await?;
+        }
+
+        Ok(engine)
+    }
+
+    /// Loads a listing's orderbook from its latest snapshot and journal, and spawns the
+    /// actor that owns it
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing` - The listing to load
+    async fn load(&self, listing: Listing) -> Result<(), MarketError> {
+        let listing_id = listing._id.clone();
+        let mut order_book = load_order_book(&self.db, listing_id.clone()).await?;
+        order_book.fees = self.fees.rates_for(&listing_id);
+        order_book.tick_size = listing.tick_size;
+
+        let handle = spawn_listing_actor(listing_id.clone(), order_book, self.db.clone());
+        self.books.lock().await.insert(listing_id.clone(), handle);
+        self.listings.lock().await.insert(listing_id, listing);
+
+        Ok(())
+    }
+
+    /// Gets the handle of the actor that owns a listing's orderbook
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing
+    async fn handle(&self, listing_id: &str) -> Result<ListingHandle, MarketError> {
+        construct_mongodb_object_id(listing_id.to_string())?;
+
+        match self.books.lock().await.get(listing_id) {
+            Some(handle) => Ok(handle.clone()),
+            None => Err(MarketError::NotFound(
+                "Couldn't find orderbook with given ID".to_string(),
+            )),
+        }
+    }
+
+    /// Gets the handles of every running actor, by listing ID
+    async fn handles(&self) -> Vec<(String, ListingHandle)> {
+        let books = self.books.lock().await;
+        books
+            .iter()
+            .map(|(id, h)| (id.clone(), h.clone()))
+            .collect()
+    }
+
+    /// Matches a barter order against its own orderbook and the orderbook of the listing
+    /// it wants, borrowing both from their actors for the duration
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The barter order to match
+    async fn add_barter_order(&self, mut order: Order) -> Result<OrderOutcome, MarketError> {
+        let _barter = self.barter_lock.lock().await;
+        let own = self.handle(&order.listing_id).await?;
+        let desired_id = order.desired_listing_id.clone().unwrap_or_default();
+
+        // A listing's own orderbook is never borrowed twice, as its actor would wait on
+        // the first loan forever
+        let counter = match desired_id == order.listing_id {
+            true => None,
+            false => self.handle(&desired_id).await.ok(),
+        };
+
+        let mut counter = match counter {
+            Some(counter) => Some(counter.borrow().await?),
+            None => None,
+        };
+        let (mut order_book, give_back) = own.borrow().await?;
+
+        let at = Utc::now().timestamp();
+        let accepted = OrderEvent::OrderAccepted {
+            order: order.clone(),
+            at,
+        };
+        let outcome = order_book.match_barter(counter.as_mut().map(|(c, _)| c), &mut order, at);
+
+        // Only the order's own orderbook journals it arriving. The counter orderbook
+        // journals the orders of its own that were held against it
+        let _ = give_back.send((order_book, Some(accepted)));
+        if let Some((counter, give_back)) = counter {
+            let _ = give_back.send((counter, None));
+        }
+
+        Ok(outcome)
+    }
+}
+
+#[async_trait]
+impl<D: MarketDatabase + Clone + Send + Sync + 'static> MarketDatabase for MatchingEngine<D> {
+    async fn get_listings(&self) -> Result<Vec<Listing>, MarketError> {
+        self.db.lock().await.get_listings().await
+    }
+
+    async fn add_listing(&self, listing: Listing) -> Result<(), MarketError> {
+        let listing_id = listing._id.clone();
+        let db = self.db.lock().await.clone();
+        db.add_listing(listing).await?;
+
+        // The listing is loaded as stored, in case the database normalized it
+        let listing = db.get_listing_by_id(listing_id).await?;
+        self.load(listing).await
+    }
+
+    async fn get_listing_by_id(&self, id: String) -> Result<Listing, MarketError> {
+        construct_mongodb_object_id(id.clone())?;
+
+        match self.listings.lock().await.get(&id) {
+            Some(listing) => Ok(listing.clone()),
+            None => Err(MarketError::NotFound(
+                "Couldn't find listing with given ID".to_string(),
+            )),
+        }
+    }
+
+    async fn get_orders_by_id(&self, id: String) -> Result<OrderBook, MarketError> {
+        let handle = self.handle(&id).await?;
+        handle.request(|reply| BookCommand::Get { reply }).await
+    }
+
+    async fn add_order(&self, order: Order) -> Result<OrderOutcome, MarketError> {
+        if order.is_barter() {
+            return self.add_barter_order(order).await;
+        }
+
+        let handle = self.handle(&order.listing_id).await?;
+        handle
+            .request(|reply| BookCommand::AddOrder { order, reply })
+            .await?
+    }
+
+    async fn cancel_order(
+        &self,
+        listing_id: String,
+        order_id: String,
+    ) -> Result<Order, MarketError> {
+        let handle = self.handle(&listing_id).await?;
+        let cancelled = handle
+            .request(|reply| BookCommand::CancelOrder { order_id, reply })
+            .await?;
+
+        cancelled.ok_or(MarketError::NotFound(
+            "Couldn't find order with given ID".to_string(),
+        ))
+    }
+
+    async fn amend_order(
+        &self,
+        listing_id: String,
+        order_id: String,
+        amendment: OrderAmendment,
+    ) -> Result<Order, MarketError> {
+        let handle = self.handle(&listing_id).await?;
+        handle
+            .request(|reply| BookCommand::AmendOrder {
+                order_id,
+                amendment,
+                reply,
+            })
+            .await?
+    }
+
+    async fn get_pending_trades_by_id(&self, id: String) -> Result<Vec<PendingTrade>, MarketError> {
+        Ok(self.get_orders_by_id(id).await?.pending_trades)
+    }
+
+    async fn update_trade_status(
+        &self,
+        druid: String,
+        status: TradeStatus,
+    ) -> Result<PendingTrade, MarketError> {
+        let mut trade = None;
+
+        // Barter trades are recorded in the orderbook of every listing they exchange
+        for (_, handle) in self.handles().await {
+            let druid = druid.clone();
+            let updated = handle
+                .request(|reply| BookCommand::UpdateTradeStatus {
+                    druid,
+                    status,
+                    reply,
+                })
+                .await?;
+
+            if let Some(updated) = updated {
+                trade = Some(updated?);
+            }
+        }
+
+        trade.ok_or(MarketError::NotFound(
+            "Couldn't find trade with given DRUID".to_string(),
+        ))
+    }
+
+    async fn purge_expired_orders(&self, now: i64) -> Result<usize, MarketError> {
+        let mut removed = 0;
+
+        for (_, handle) in self.handles().await {
+            removed += handle
+                .request(|reply| BookCommand::PurgeExpired { now, reply })
+                .await?;
+        }
+
+        Ok(removed)
+    }
+
+    async fn expire_pending_trades(&self, now: i64) -> Result<usize, MarketError> {
+        let mut expired = 0;
+
+        for (_, handle) in self.handles().await {
+            expired += handle
+                .request(|reply| BookCommand::ExpireTrades { now, reply })
+                .await?;
+        }
+
+        Ok(expired)
+    }
+
+    async fn match_barter_rings(&self, now: i64) -> Result<Vec<PendingTrade>, MarketError> {
+        let _barter = self.barter_lock.lock().await;
+        let mut books = BTreeMap::new();
+        let mut give_backs = BTreeMap::new();
+
+        for (listing_id, handle) in self.handles().await {
+            let (order_book, give_back) = handle.borrow().await?;
+            books.insert(listing_id.clone(), order_book);
+            give_backs.insert(listing_id, give_back);
+        }
+
+        let trades = match_rings(&mut books, now);
+
+        for (listing_id, order_book) in books {
+            if let Some(give_back) = give_backs.remove(&listing_id) {
+                let _ = give_back.send((order_book, None));
+            }
+        }
+
+        Ok(trades)
+    }
+
+    async fn get_fee_reports(&self) -> Result<Vec<FeeReport>, MarketError> {
+        let mut reports = Vec::new();
+
+        for (listing_id, handle) in self.handles().await {
+            let order_book = handle.request(|reply| BookCommand::Get { reply }).await?;
+            reports.push(FeeReport::from_order_book(listing_id, &order_book));
+        }
+
+        Ok(reports)
+    }
+
+    async fn save_order_book(&self, id: String, order_book: OrderBook) -> Result<(), MarketError> {
+        // The orderbook is handed to its actor, which writes it to the database in turn
+        let handle = self.handle(&id).await?;
+        handle
+            .request(|reply| BookCommand::Replace { order_book, reply })
+            .await
+    }
+
+    async fn append_journal(&self, entries: Vec<JournalEntry>) -> Result<(), MarketError> {
+        self.db.lock().await.append_journal(entries).await
+    }
+
+    async fn get_journal(
+        &self,
+        listing_id: String,
+        from: u64,
+    ) -> Result<Vec<JournalEntry>, MarketError> {
+        self.db.lock().await.get_journal(listing_id, from).await
+    }
+
+    async fn save_snapshot(&self, snapshot: Snapshot) -> Result<(), MarketError> {
+        self.db.lock().await.save_snapshot(snapshot).await
+    }
+
+    async fn get_snapshots(&self, listing_id: String) -> Result<Vec<Snapshot>, MarketError> {
+        self.db.lock().await.get_snapshots(listing_id).await
+    }
+
+    async fn compact_journal(&self, listing_id: String, before: u64) -> Result<u64, MarketError> {
+        self.db
+            .lock()
+            .await
+            .compact_journal(listing_id, before)
+            .await
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::InMemoryMarket;
+    use crate::market::amount::Amount;
+    use futures::future::join_all;
+
+    const LISTING_A: &str = "a8f163782fb07c69f511248e";
+    const LISTING_B: &str = "b8f163782fb07c69f511248e";
+
+    fn create_listing(id: &str) -> Listing {
+        Listing {
+            _id: id.to_string(),
+            title: String::from("Asset_test"),
+            initial_price: Amount::new(10),
+            quantity: Amount::new(6),
+            ..Default::default()
+        }
+    }
+
+    fn create_order(listing_id: &str, price: u64, quantity: u64, is_bid: bool) -> Order {
+        Order {
+            id: format!("{listing_id}-{price}-{quantity}"),
+            listing_id: listing_id.to_string(),
+            price: Amount::new(price),
+            quantity: Amount::new(quantity),
+            is_bid,
+            ..Default::default()
+        }
+    }
+
+    fn create_barter(listing_id: &str, desired_id: &str, price: u64, quantity: u64) -> Order {
+        Order {
+            desired_listing_id: Some(desired_id.to_string()),
+            ..create_order(listing_id, price, quantity, false)
+        }
+    }
+
+    #[test]
+    fn should_match_listings_in_parallel_and_barter_across_them() {
+        //
+        // Arrange
+        //
+        let db = InMemoryMarket::new();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        //
+        // Act
+        //
+        let (bids, swap, book_a, book_b, listing) = runtime.block_on(async {
+            db.add_listing(create_listing(LISTING_A)).await.unwrap();
+            let engine = MatchingEngine::start(Arc::new(Mutex::new(db)), FeeSchedule::default())
+                .await
+                .unwrap();
+            engine.add_listing(create_listing(LISTING_B)).await.unwrap();
+
+            let bids = join_all(
+                [LISTING_A, LISTING_B, LISTING_A, LISTING_B]
+                    .into_iter()
+                    .map(|id| engine.add_order(create_order(id, 10, 3, true))),
+            )
+            .await;
+
+            engine
+                .add_order(create_barter(LISTING_A, LISTING_B, 3, 5))
+                .await
+                .unwrap();
+            let swap = engine
+                .add_order(create_barter(LISTING_B, LISTING_A, 5, 3))
+                .await
+                .unwrap();
+
+            let book_a = engine.get_orders_by_id(LISTING_A.to_string()).await;
+            let book_b = engine.get_orders_by_id(LISTING_B.to_string()).await;
+            let listing = engine.get_listing_by_id(LISTING_B.to_string()).await;
+            (
+                bids,
+                swap,
+                book_a.unwrap(),
+                book_b.unwrap(),
+                listing.unwrap(),
+            )
+        });
+
+        //
+        // Assert
+        //
+        assert!(bids
+            .iter()
+            .all(|o| o.as_ref().unwrap().filled == Amount::new(3)));
+        assert!(book_a.best_ask().is_none());
+        assert!(book_b.best_ask().is_none());
+        assert_eq!(swap.trades.len(), 1);
+        assert_eq!(swap.trades[0].legs.len(), 2);
+        assert!(book_a
+            .pending_trades
+            .iter()
+            .any(|t| t.druid == swap.trades[0].druid));
+        assert!(book_b
+            .pending_trades
+            .iter()
+            .any(|t| t.druid == swap.trades[0].druid));
+        assert_eq!(listing._id, LISTING_B);
+    }
+}
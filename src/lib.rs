@@ -1,6 +1,7 @@
 pub mod api;
 pub mod constants;
 pub mod db;
+pub mod engine;
 pub mod market;
 pub mod utils;
 #[cfg(test)]
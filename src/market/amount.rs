@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// An exact fixed-point amount, stored as an integer number of base units.
+///
+/// The number of decimal places a base unit represents is set per listing
+/// (see `Listing::decimals`), so `Amount::new(150)` on a listing with 2 decimals
+/// is `1.50`. Serializes as a plain integer in both JSON and BSON
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// The largest amount that can be stored, as BSON integers are signed
+    pub const MAX: Amount = Amount(i64::MAX as u64);
+
+    /// Creates an amount from a number of base units
+    ///
+    /// ### Arguments
+    ///
+    /// * `units` - The number of base units
+    pub const fn new(units: u64) -> Self {
+        Amount(units)
+    }
+
+    /// Returns the number of base units in this amount
+    pub const fn units(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns true if this amount has no base units
+    pub const fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Adds `other` to this amount, returning `None` on overflow
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The amount to add
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    /// Adds `other` to this amount, stopping at the largest amount
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The amount to add
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    /// Subtracts `other` from this amount, returning `None` on underflow
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The amount to subtract
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// Subtracts `other` from this amount, stopping at zero
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The amount to subtract
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+
+    /// Returns true if this amount is a whole number of `step`s. Any amount is a
+    /// multiple of a zero step
+    ///
+    /// ### Arguments
+    ///
+    /// * `step` - The increment to check against
+    pub fn is_multiple_of(self, step: Amount) -> bool {
+        step.0 == 0 || self.0.is_multiple_of(step.0)
+    }
+
+    /// Returns the value of `quantity` at this price, where both are in base units with
+    /// the given number of decimals. Rounds down, and saturates at the largest amount
+    ///
+    /// ### Arguments
+    ///
+    /// * `quantity` - The quantity priced
+    /// * `decimals` - The number of decimal places a base unit represents
+    pub fn notional(self, quantity: Amount, decimals: u32) -> Amount {
+        let scale = 10u128.checked_pow(decimals).unwrap_or(u128::MAX);
+        let value = self.0 as u128 * quantity.0 as u128 / scale;
+
+        Amount(value.min(u64::MAX as u128) as u64)
+    }
+
+    /// Converts a legacy floating point value into base units, rounding to the
+    /// nearest unit. Returns `None` for negative, non-finite or out of range values
+    ///
+    /// ### Arguments
+    ///
+    /// * `value` - The floating point value to convert
+    /// * `decimals` - The number of decimal places a base unit represents
+    pub fn from_f64(value: f64, decimals: u32) -> Option<Amount> {
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+
+        let units = (value * 10f64.powi(decimals as i32)).round();
+        if units > u64::MAX as f64 {
+            return None;
+        }
+
+        Some(Amount(units as u64))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        self.0 -= other.0;
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_legacy_floats_exactly() {
+        //
+        // Arrange
+        //
+        let a = Amount::from_f64(0.1, 2).unwrap();
+        let b = Amount::from_f64(0.2, 2).unwrap();
+
+        //
+        // Act
+        //
+        let sum = a + b;
+
+        //
+        // Assert
+        //
+        assert_eq!(sum, Amount::from_f64(0.3, 2).unwrap());
+        assert_eq!(sum, Amount::new(30));
+        assert!((sum - Amount::new(30)).is_zero());
+    }
+
+    #[test]
+    fn should_reject_invalid_legacy_floats() {
+        assert!(Amount::from_f64(-1.0, 2).is_none());
+        assert!(Amount::from_f64(f64::NAN, 2).is_none());
+        assert!(Amount::from_f64(f64::INFINITY, 2).is_none());
+    }
+
+    #[test]
+    fn should_not_overflow_large_amounts() {
+        let max = Amount::new(u64::MAX);
+
+        assert!(max.checked_add(Amount::new(1)).is_none());
+        assert_eq!(max.saturating_add(max), max);
+        assert!(max.is_multiple_of(Amount::new(5)));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::constants::MAX_FEE_BPS;
+use crate::market::amount::Amount;
+use crate::market::interfaces::{OrderBook, PendingTrade, TradeStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Basis points in one whole
+const BPS_PER_UNIT: u128 = 10_000;
+
+/// Reasons a fee rate can't be charged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeeRateError {
+    /// The maker rate is above the highest allowed rate
+    MakerAboveMax(u32),
+    /// The taker rate is above the highest allowed rate
+    TakerAboveMax(u32),
+}
+
+impl fmt::Display for FeeRateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeeRateError::MakerAboveMax(bps) => {
+                write!(
+                    f,
+                    "Maker fee of {bps} bps is above the maximum of {MAX_FEE_BPS} bps"
+                )
+            }
+            FeeRateError::TakerAboveMax(bps) => {
+                write!(
+                    f,
+                    "Taker fee of {bps} bps is above the maximum of {MAX_FEE_BPS} bps"
+                )
+            }
+        }
+    }
+}
+
+/// The maker and taker fee rates charged on each trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FeeRates {
+    /// Fee charged to the resting order, in basis points of the trade value
+    pub maker_bps: u32,
+    /// Fee charged to the incoming order, in basis points of the trade value
+    pub taker_bps: u32,
+    /// The smallest fee charged to any side of a trade that pays a fee
+    pub min_fee: Amount,
+}
+
+impl FeeRates {
+    /// Checks that neither rate charges more than the whole value of a trade
+    pub fn validate(&self) -> Result<(), FeeRateError> {
+        if self.maker_bps > MAX_FEE_BPS {
+            return Err(FeeRateError::MakerAboveMax(self.maker_bps));
+        }
+
+        if self.taker_bps > MAX_FEE_BPS {
+            return Err(FeeRateError::TakerAboveMax(self.taker_bps));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the maker and taker fees for a trade
+    ///
+    /// ### Arguments
+    ///
+    /// * `price` - The price of the trade
+    /// * `quantity` - The quantity traded
+    /// * `decimals` - The number of decimal places a base unit represents
+    pub fn charge(&self, price: Amount, quantity: Amount, decimals: u32) -> (Amount, Amount) {
+        let value = price.notional(quantity, decimals);
+        (
+            self.fee(self.maker_bps, value),
+            self.fee(self.taker_bps, value),
+        )
+    }
+
+    /// Returns the fee at the given rate, raised to the minimum fee. Sides with no
+    /// rate pay nothing
+    ///
+    /// ### Arguments
+    ///
+    /// * `bps` - The fee rate in basis points
+    /// * `value` - The value of the trade
+    fn fee(&self, bps: u32, value: Amount) -> Amount {
+        if bps == 0 {
+            return Amount::ZERO;
+        }
+
+        // Rates are validated when the schedule is built, but one read from elsewhere
+        // could still exceed the trade value, so the fee is capped rather than truncated
+        let fee = value.units() as u128 * bps as u128 / BPS_PER_UNIT;
+        Amount::new(u64::try_from(fee).unwrap_or(u64::MAX)).max(self.min_fee)
+    }
+}
+
+/// The fee rates charged across the market, with optional per-listing overrides
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FeeSchedule {
+    /// The rates for listings without an override
+    pub default: FeeRates,
+    /// Rates for specific listings, keyed by listing ID
+    #[serde(default)]
+    pub overrides: HashMap<String, FeeRates>,
+}
+
+impl FeeSchedule {
+    /// Creates a fee schedule charging the given rates on every listing, failing if
+    /// either rate is above the highest allowed rate
+    ///
+    /// ### Arguments
+    ///
+    /// * `default` - The rates for listings without an override
+    pub fn new(default: FeeRates) -> Result<Self, FeeRateError> {
+        default.validate()?;
+
+        Ok(FeeSchedule {
+            default,
+            overrides: HashMap::new(),
+        })
+    }
+
+    /// Sets the rates charged on a specific listing, failing if either rate is above
+    /// the highest allowed rate
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing
+    /// * `rates` - The rates for the listing
+    pub fn with_override(
+        mut self,
+        listing_id: String,
+        rates: FeeRates,
+    ) -> Result<Self, FeeRateError> {
+        rates.validate()?;
+        self.overrides.insert(listing_id, rates);
+        Ok(self)
+    }
+
+    /// Checks every rate in the schedule, including those read from elsewhere
+    pub fn validate(&self) -> Result<(), FeeRateError> {
+        self.default.validate()?;
+        self.overrides.values().try_for_each(FeeRates::validate)
+    }
+
+    /// Returns the rates charged on a listing
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing
+    pub fn rates_for(&self, listing_id: &str) -> FeeRates {
+        self.overrides
+            .get(listing_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Running totals of the fees earned on confirmed trades
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FeeTotals {
+    /// The number of confirmed trades
+    pub confirmed_trades: usize,
+    /// Maker fees earned on confirmed trades
+    pub maker_fees: Amount,
+    /// Taker fees earned on confirmed trades
+    pub taker_fees: Amount,
+}
+
+impl FeeTotals {
+    /// Adds a settled trade's fees to the totals, if the trade was confirmed. Fees on
+    /// failed and expired trades are never collected, so are left out
+    ///
+    /// ### Arguments
+    ///
+    /// * `trade` - The settled trade
+    pub fn add(&mut self, trade: &PendingTrade) {
+        if trade.status == TradeStatus::Confirmed {
+            self.confirmed_trades += 1;
+            self.maker_fees = self.maker_fees.saturating_add(trade.maker_fee);
+            self.taker_fees = self.taker_fees.saturating_add(trade.taker_fee);
+        }
+    }
+}
+
+/// The fees charged on a listing's trades
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FeeReport {
+    pub listing_id: String,
+    /// The number of confirmed trades
+    pub confirmed_trades: usize,
+    /// Maker fees earned on confirmed trades
+    pub maker_fees: Amount,
+    /// Taker fees earned on confirmed trades
+    pub taker_fees: Amount,
+    /// Maker and taker fees on trades still awaiting settlement
+    pub pending_fees: Amount,
+}
+
+impl FeeReport {
+    /// Aggregates the fees charged on an orderbook's trades, including those of trades
+    /// already pruned from its settled list. Fees on failed and expired trades are
+    /// never collected, so are left out
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing the orderbook belongs to
+    /// * `order_book` - The orderbook to report on
+    pub fn from_order_book(listing_id: String, order_book: &OrderBook) -> Self {
+        let mut totals = order_book.pruned_fees;
+        for trade in &order_book.settled_trades {
+            totals.add(trade);
+        }
+
+        let mut report = FeeReport {
+            listing_id,
+            confirmed_trades: totals.confirmed_trades,
+            maker_fees: totals.maker_fees,
+            taker_fees: totals.taker_fees,
+            ..Default::default()
+        };
+
+        for trade in &order_book.pending_trades {
+            report.pending_fees = report
+                .pending_fees
+                .saturating_add(trade.maker_fee)
+                .saturating_add(trade.taker_fee);
+        }
+
+        report
+    }
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::MAX_SETTLED_TRADES;
+    use crate::market::interfaces::Order;
+
+    #[test]
+    fn should_charge_rates_with_minimum_fee() {
+        //
+        // Arrange
+        //
+        let rates = FeeRates {
+            maker_bps: 10,
+            taker_bps: 25,
+            min_fee: Amount::new(3),
+        };
+        let schedule = FeeSchedule::new(FeeRates::default())
+            .and_then(|schedule| schedule.with_override(String::from("1"), rates))
+            .unwrap();
+
+        //
+        // Act
+        //
+        let (maker, taker) =
+            schedule
+                .rates_for("1")
+                .charge(Amount::new(200), Amount::new(1_000), 2);
+        let (free_maker, free_taker) =
+            schedule
+                .rates_for("2")
+                .charge(Amount::new(200), Amount::new(1_000), 2);
+
+        //
+        // Assert
+        //
+        assert_eq!(maker, Amount::new(3));
+        assert_eq!(taker, Amount::new(5));
+        assert!(free_maker.is_zero() && free_taker.is_zero());
+    }
+
+    #[test]
+    fn should_reject_rates_above_the_trade_value() {
+        //
+        // Arrange
+        //
+        let rates = FeeRates {
+            maker_bps: MAX_FEE_BPS,
+            taker_bps: MAX_FEE_BPS + 1,
+            min_fee: Amount::ZERO,
+        };
+        let unchecked = FeeRates {
+            maker_bps: u32::MAX,
+            ..Default::default()
+        };
+
+        //
+        // Act
+        //
+        let schedule = FeeSchedule::new(rates);
+        let (maker, _) = unchecked.charge(Amount::new(u64::MAX), Amount::new(u64::MAX), 0);
+
+        //
+        // Assert
+        //
+        assert_eq!(schedule, Err(FeeRateError::TakerAboveMax(MAX_FEE_BPS + 1)));
+        assert_eq!(maker, Amount::new(u64::MAX));
+    }
+
+    #[test]
+    fn should_report_fees_of_pruned_settled_trades() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        order_book.fees = FeeRates {
+            maker_bps: 100,
+            taker_bps: 200,
+            min_fee: Amount::ZERO,
+        };
+        let trades = MAX_SETTLED_TRADES + 5;
+
+        //
+        // Act
+        //
+        for i in 0..trades {
+            let mut ask = Order {
+                id: format!("ask{i}"),
+                price: Amount::new(100),
+                quantity: Amount::new(1),
+                ..Default::default()
+            };
+            let mut bid = Order {
+                id: format!("bid{i}"),
+                is_bid: true,
+                ..ask.clone()
+            };
+            order_book.add_order(&mut ask).unwrap();
+            let outcome = order_book.add_order(&mut bid).unwrap();
+            order_book
+                .update_trade_status(&outcome.trades[0].druid, TradeStatus::Confirmed)
+                .unwrap();
+        }
+        let report = FeeReport::from_order_book(String::from("1"), &order_book);
+
+        //
+        // Assert
+        //
+        assert_eq!(order_book.settled_trades.len(), MAX_SETTLED_TRADES);
+        assert_eq!(order_book.pruned_fees.confirmed_trades, 5);
+        assert_eq!(report.confirmed_trades, trades);
+        assert_eq!(report.maker_fees, Amount::new(trades as u64));
+        assert_eq!(report.taker_fees, Amount::new(2 * trades as u64));
+    }
+}
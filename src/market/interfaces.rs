@@ -1,348 +1,3196 @@
+use crate::constants::{MAX_SETTLED_TRADES, PENDING_TRADE_TIMEOUT};
+use crate::market::amount::Amount;
+use crate::market::fees::{FeeRates, FeeTotals};
 use crate::utils::construct_druid;
-use serde::{Deserialize, Serialize};
+use chrono::prelude::{TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
 
-/// An asset listing on the market
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// An asset listing on the market. All prices and quantities on the listing and its
+/// orderbook are in base units, where one whole unit is `10^decimals` base units
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Listing {
     pub _id: String,
     pub title: String,
     pub description: String,
-    pub initial_price: f64,
-    pub quantity: f64,
+    pub initial_price: Amount,
+    pub quantity: Amount,
+    #[serde(default)]
+    pub decimals: u32,
+    /// The increment order prices must be a multiple of. Zero allows any price
+    #[serde(default)]
+    pub tick_size: Amount,
+    /// The increment order quantities must be a multiple of. Zero allows any quantity
+    #[serde(default)]
+    pub lot_size: Amount,
+    /// The smallest quantity an order may be placed for
+    #[serde(default)]
+    pub min_quantity: Amount,
+    /// The smallest value, price times quantity, a priced order may be placed for
+    #[serde(default)]
+    pub min_notional: Amount,
+}
+
+impl Listing {
+    /// Checks an order against this listing's tick size, lot size, minimum quantity
+    /// and minimum notional. Market and stop orders have no price of their own, so
+    /// only their quantity is checked, as is the case for barter orders, whose price
+    /// is in the desired listing's units
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to check
+    pub fn check_order(&self, order: &Order) -> Result<(), ListingRuleError> {
+        let quantity = order.total_quantity();
+        let display = order.display_quantity.unwrap_or(self.lot_size);
+
+        if !quantity.is_multiple_of(self.lot_size) || !display.is_multiple_of(self.lot_size) {
+            return Err(ListingRuleError::OffLot(quantity, self.lot_size));
+        }
+
+        if quantity < self.min_quantity {
+            return Err(ListingRuleError::BelowMinQuantity(
+                quantity,
+                self.min_quantity,
+            ));
+        }
+
+        if order.is_barter() {
+            return Ok(());
+        }
+
+        if let Some(trigger) = order.trigger_price {
+            if !trigger.is_multiple_of(self.tick_size) {
+                return Err(ListingRuleError::TriggerOffTick(trigger, self.tick_size));
+            }
+        }
+
+        if !matches!(order.order_type, OrderType::Limit | OrderType::StopLimit) {
+            return Ok(());
+        }
+
+        if !order.price.is_multiple_of(self.tick_size) {
+            return Err(ListingRuleError::OffTick(order.price, self.tick_size));
+        }
+
+        let notional = order.price.notional(quantity, self.decimals);
+        if notional < self.min_notional {
+            return Err(ListingRuleError::BelowMinNotional(
+                notional,
+                self.min_notional,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reasons an order doesn't conform to its listing's trading rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListingRuleError {
+    /// The price isn't a multiple of the tick size
+    OffTick(Amount, Amount),
+    /// The trigger price isn't a multiple of the tick size
+    TriggerOffTick(Amount, Amount),
+    /// A quantity isn't a multiple of the lot size
+    OffLot(Amount, Amount),
+    /// The quantity is below the minimum order quantity
+    BelowMinQuantity(Amount, Amount),
+    /// The value of the order is below the minimum notional
+    BelowMinNotional(Amount, Amount),
+}
+
+impl fmt::Display for ListingRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListingRuleError::OffTick(price, tick) => {
+                write!(f, "Price {price} is not a multiple of the tick size {tick}")
+            }
+            ListingRuleError::TriggerOffTick(price, tick) => {
+                write!(
+                    f,
+                    "Trigger price {price} is not a multiple of the tick size {tick}"
+                )
+            }
+            ListingRuleError::OffLot(quantity, lot) => {
+                write!(
+                    f,
+                    "Quantity {quantity} is not a multiple of the lot size {lot}"
+                )
+            }
+            ListingRuleError::BelowMinQuantity(quantity, min) => {
+                write!(
+                    f,
+                    "Quantity {quantity} is below the minimum order quantity {min}"
+                )
+            }
+            ListingRuleError::BelowMinNotional(notional, min) => {
+                write!(
+                    f,
+                    "Order value {notional} is below the minimum notional {min}"
+                )
+            }
+        }
+    }
+}
+
+/// The settlement state of a matched trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+    /// Matched, with settlement not yet started
+    #[default]
+    Pending,
+    /// Waiting for both parties to sign the DRUID transaction
+    AwaitingSignatures,
+    /// The signed transaction has been submitted to the chain
+    Submitted,
+    /// The transaction has been confirmed on chain
+    Confirmed,
+    /// Settlement failed, and the matched quantity is returned to the orders
+    Failed,
+    /// Settlement didn't happen in time, and the matched quantity is returned to the orders
+    Expired,
+}
+
+impl TradeStatus {
+    /// Returns true once a trade can no longer change state
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TradeStatus::Confirmed | TradeStatus::Failed | TradeStatus::Expired
+        )
+    }
+
+    /// Returns true if a trade in this state may move to `next`. Trades only move
+    /// forward, and a submitted trade can no longer expire
+    ///
+    /// ### Arguments
+    ///
+    /// * `next` - The state to move to
+    pub fn can_transition_to(&self, next: TradeStatus) -> bool {
+        use TradeStatus::*;
+
+        matches!(
+            (self, next),
+            (Pending, AwaitingSignatures)
+                | (Pending | AwaitingSignatures, Submitted)
+                | (Pending | AwaitingSignatures | Submitted, Confirmed)
+                | (Pending | AwaitingSignatures | Submitted, Failed)
+                | (Pending | AwaitingSignatures, Expired)
+        )
+    }
+}
+
+impl fmt::Display for TradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TradeStatus::Pending => write!(f, "pending"),
+            TradeStatus::AwaitingSignatures => write!(f, "awaiting_signatures"),
+            TradeStatus::Submitted => write!(f, "submitted"),
+            TradeStatus::Confirmed => write!(f, "confirmed"),
+            TradeStatus::Failed => write!(f, "failed"),
+            TradeStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PendingTrade {
+    pub bid_id: String,
+    pub ask_id: String,
+    pub quantity: Amount,
+    pub price: Amount,
+    pub created_at: String,
+    pub druid: String,
+    #[serde(default)]
+    pub status: TradeStatus,
+    /// Unix timestamp in seconds after which an unsubmitted trade expires
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// Whether the resting order the trade was matched against is the bid
+    #[serde(default)]
+    pub maker_is_bid: bool,
+    /// For barter trades, the asset each order delivers in the swap
+    #[serde(default)]
+    pub legs: Vec<TradeLeg>,
+    /// The fee charged to the resting order. Barter trades are charged no fees
+    #[serde(default)]
+    pub maker_fee: Amount,
+    /// The fee charged to the incoming order. Barter trades are charged no fees
+    #[serde(default)]
+    pub taker_fee: Amount,
+}
+
+impl PendingTrade {
+    /// Creates a pending barter trade between orders that each deliver to the next,
+    /// with the last delivering to the first. Each order delivers exactly what the
+    /// next one wants. The first order is recorded as the ask and the second as the bid,
+    /// with `quantity` being what the first order delivers and `price` what it receives.
+    /// No fees are charged, as there's no payment to take them from
+    ///
+    /// ### Arguments
+    ///
+    /// * `orders` - The barter orders in the swap, in delivery order
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn barter(orders: &[Order], now: i64) -> PendingTrade {
+        let legs: Vec<TradeLeg> = orders
+            .iter()
+            .zip(orders.iter().cycle().skip(1))
+            .map(|(from, to)| TradeLeg {
+                order_id: from.id.clone(),
+                listing_id: from.gives().0.to_string(),
+                quantity: to.wants().1,
+            })
+            .collect();
+
+        PendingTrade {
+            bid_id: orders.get(1).map(|o| o.id.clone()).unwrap_or_default(),
+            ask_id: orders.first().map(|o| o.id.clone()).unwrap_or_default(),
+            quantity: legs.first().map(|l| l.quantity).unwrap_or_default(),
+            price: legs.last().map(|l| l.quantity).unwrap_or_default(),
+            created_at: format_timestamp(now),
+            druid: construct_druid(),
+            status: TradeStatus::Pending,
+            expires_at: Some(now.saturating_add(PENDING_TRADE_TIMEOUT)),
+            maker_is_bid: false,
+            legs,
+            maker_fee: Amount::ZERO,
+            taker_fee: Amount::ZERO,
+        }
+    }
+}
+
+/// One asset transfer in a barter trade
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TradeLeg {
+    /// The ID of the order delivering the asset
+    pub order_id: String,
+    /// The ID of the listing whose asset is delivered
+    pub listing_id: String,
+    /// The amount of the asset delivered
+    pub quantity: Amount,
+}
+
+/// How an order is priced when matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    /// Matches at its own price or better, resting any remainder according to its time-in-force
+    #[default]
+    Limit,
+    /// Sweeps the opposite side at any price and never rests
+    Market,
+    /// Waits in the trigger book until a trade reaches its trigger price, then becomes a
+    /// market order
+    Stop,
+    /// Waits in the trigger book until a trade reaches its trigger price, then becomes a
+    /// limit order at its price
+    StopLimit,
+}
+
+/// How long an order remains active for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    /// Good-till-cancelled: any unfilled remainder rests on the book
+    #[default]
+    Gtc,
+    /// Immediate-or-cancel: fills what it can immediately and discards the remainder
+    Ioc,
+    /// Fill-or-kill: fills completely and immediately, or is rejected without matching
+    Fok,
+}
+
+/// How a post-only (maker-only) order is handled if it would match on arrival
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PostOnly {
+    /// The order may match on arrival
+    #[default]
+    Disabled,
+    /// The order is rejected if it would match on arrival
+    Reject,
+    /// The order is repriced to one tick behind the best opposite quote
+    Reprice,
+}
+
+/// What happens when an incoming order would match a resting order with the same owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTradePrevention {
+    /// Cancel the remainder of the incoming order
+    #[default]
+    CancelNewest,
+    /// Cancel the resting order and carry on matching
+    CancelOldest,
+    /// Cancel both the resting order and the remainder of the incoming order
+    CancelBoth,
+    /// Reduce both orders by the overlapping quantity without trading
+    Decrement,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Order {
+    /// Assigned by the server when the order is submitted
+    #[serde(default)]
+    pub id: String,
+    pub listing_id: String,
+    pub price: Amount,
+    pub quantity: Amount,
+    pub is_bid: bool,
+    /// Assigned by the server when the order is submitted
+    #[serde(default)]
+    pub created_at: String,
+    pub druid: Option<String>,
+    pub desired_listing_id: Option<String>,
+    #[serde(default)]
+    pub order_type: OrderType,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    #[serde(default)]
+    pub post_only: PostOnly,
+    /// Unix timestamp in seconds after which the order is no longer matched (good-till-date)
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// The last trade price that activates a stop or stop-limit order
+    #[serde(default)]
+    pub trigger_price: Option<Amount>,
+    /// For iceberg orders, the largest quantity shown on the book at any one time
+    #[serde(default)]
+    pub display_quantity: Option<Amount>,
+    /// For iceberg orders, the hidden quantity used to replenish the displayed slice
+    #[serde(default)]
+    pub reserve_quantity: Amount,
+    /// The identity of the user placing the order, used for self-trade prevention
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Applied by an incoming order when it would match a resting order it owns
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Matched quantity held against this order until its pending trades settle
+    #[serde(default)]
+    pub reserved_quantity: Amount,
+    /// Time priority assigned by the orderbook, where lower values arrived first
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+impl Order {
+    /// Returns true if this order would match against a resting order at the given price
+    ///
+    /// ### Arguments
+    ///
+    /// * `price` - The price of the resting order on the opposite side
+    pub fn crosses(&self, price: Amount) -> bool {
+        match self.order_type {
+            OrderType::Market | OrderType::Stop => true,
+            OrderType::Limit | OrderType::StopLimit if self.is_bid => price <= self.price,
+            OrderType::Limit | OrderType::StopLimit => price >= self.price,
+        }
+    }
+
+    /// Returns true if this order waits in the trigger book before it can be matched
+    pub fn is_stop(&self) -> bool {
+        matches!(self.order_type, OrderType::Stop | OrderType::StopLimit)
+    }
+
+    /// Returns true if a trade at the given price activates this order. Buy stops
+    /// trigger at or above their trigger price, and sell stops at or below it
+    ///
+    /// ### Arguments
+    ///
+    /// * `price` - The price of the trade
+    pub fn is_triggered_by(&self, price: Amount) -> bool {
+        match self.trigger_price {
+            Some(trigger) if self.is_bid => price >= trigger,
+            Some(trigger) => price <= trigger,
+            None => true,
+        }
+    }
+
+    /// Converts a triggered stop order into the order it becomes once active
+    pub fn activate(&mut self) {
+        self.order_type = match self.order_type {
+            OrderType::Stop => OrderType::Market,
+            OrderType::StopLimit => OrderType::Limit,
+            order_type => order_type,
+        };
+    }
+
+    /// Returns true if both orders have the same known owner
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The order to compare owners with
+    pub fn same_owner(&self, other: &Order) -> bool {
+        self.owner.is_some() && self.owner == other.owner
+    }
+
+    /// Returns the displayed and hidden quantity of this order combined
+    pub fn total_quantity(&self) -> Amount {
+        self.quantity.saturating_add(self.reserve_quantity)
+    }
+
+    /// Moves any quantity above an iceberg order's displayed size into its hidden reserve
+    pub fn hide_reserve(&mut self) {
+        if let Some(display) = self.display_quantity {
+            if self.quantity > display {
+                self.reserve_quantity += self.quantity - display;
+                self.quantity = display;
+            }
+        }
+    }
+
+    /// Refills an iceberg order's displayed slice from its hidden reserve
+    pub fn replenish(&mut self) {
+        let refill = match self.display_quantity {
+            Some(display) => display.min(self.reserve_quantity),
+            None => self.reserve_quantity,
+        };
+
+        self.quantity += refill;
+        self.reserve_quantity -= refill;
+    }
+
+    /// Returns a copy of this order as shown publicly, without its hidden reserve. Orders
+    /// not yet split into a displayed slice, like waiting stops, show only the slice
+    pub fn displayed(&self) -> Order {
+        let quantity = match self.display_quantity {
+            Some(display) => self.quantity.min(display),
+            None => self.quantity,
+        };
+
+        Order {
+            quantity,
+            reserve_quantity: Amount::ZERO,
+            ..self.clone()
+        }
+    }
+
+    /// Returns true if this order has passed its expiry time
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.map(|expiry| expiry <= now).unwrap_or(false)
+    }
+
+    /// Returns true if any unfilled remainder of this order may rest on the book
+    pub fn can_rest(&self) -> bool {
+        self.order_type == OrderType::Limit && self.time_in_force == TimeInForce::Gtc
+    }
+
+    /// Returns true if this order trades its listing's asset for another listing's asset
+    pub fn is_barter(&self) -> bool {
+        self.desired_listing_id
+            .as_deref()
+            .map(|desired| desired != self.listing_id)
+            .unwrap_or(false)
+    }
+
+    /// For barter orders, returns the listing and amount this order gives up. Asks give
+    /// `quantity` of their own listing, while bids give `price` of the desired listing
+    pub fn gives(&self) -> (&str, Amount) {
+        let desired = self.desired_listing_id.as_deref().unwrap_or_default();
+
+        if self.is_bid {
+            (desired, self.price)
+        } else {
+            (&self.listing_id, self.quantity)
+        }
+    }
+
+    /// For barter orders, returns the listing and amount this order wants in return. Asks
+    /// want `price` of the desired listing, while bids want `quantity` of their own listing
+    pub fn wants(&self) -> (&str, Amount) {
+        let desired = self.desired_listing_id.as_deref().unwrap_or_default();
+
+        if self.is_bid {
+            (&self.listing_id, self.quantity)
+        } else {
+            (desired, self.price)
+        }
+    }
+
+    /// Returns true if this barter order and `other` each give at least what the other
+    /// wants, in the asset the other wants
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The barter order to swap with
+    pub fn is_reciprocal(&self, other: &Order) -> bool {
+        let (gives, wants) = (self.gives(), self.wants());
+        let (other_gives, other_wants) = (other.gives(), other.wants());
+
+        other.is_barter()
+            && !self.same_owner(other)
+            && gives.0 == other_wants.0
+            && wants.0 == other_gives.0
+            && gives.1 >= other_wants.1
+            && other_gives.1 >= wants.1
+    }
+
+    /// Splits this barter order, swapped with `other`, into the part the swap delivers
+    /// and any part left over. A bid's quantity is what it wants, which a swap always
+    /// delivers in full, so only an ask can have a part left over. That part wants the
+    /// share of the ask's price its quantity makes up, rounded up, with the rest of the
+    /// price kept by the part swapped
+    ///
+    /// ### Arguments
+    ///
+    /// * `other` - The barter order swapped with
+    pub fn split_barter(&self, other: &Order) -> (Order, Option<Order>) {
+        let delivered = other.wants().1;
+        let left = match self.is_bid {
+            true => Amount::ZERO,
+            false => self.quantity.saturating_sub(delivered),
+        };
+
+        if left.is_zero() {
+            return (self.clone(), None);
+        }
+
+        let share = self.price.units() as u128 * left.units() as u128;
+        let price = Amount::new(share.div_ceil(self.quantity.units() as u128) as u64);
+        let swapped = Order {
+            quantity: delivered,
+            price: self.price - price,
+            ..self.clone()
+        };
+        let remainder = Order {
+            quantity: left,
+            price,
+            ..self.clone()
+        };
+
+        (swapped, Some(remainder))
+    }
+}
+
+/// Errors raised when an orderbook can't apply an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderBookError {
+    /// No resting order has the given ID
+    OrderNotFound(String),
+    /// A post-only order would have matched on arrival
+    PostOnlyWouldCross(String),
+    /// No pending trade has the given DRUID
+    TradeNotFound(String),
+    /// A pending trade can't move between the given settlement states
+    InvalidTradeTransition(TradeStatus, TradeStatus),
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderBookError::OrderNotFound(id) => write!(f, "Couldn't find order with ID {id}"),
+            OrderBookError::PostOnlyWouldCross(id) => {
+                write!(f, "Post-only order {id} would match on arrival")
+            }
+            OrderBookError::TradeNotFound(druid) => {
+                write!(f, "Couldn't find pending trade with DRUID {druid}")
+            }
+            OrderBookError::InvalidTradeTransition(from, to) => {
+                write!(f, "Trade can't move from {from} to {to}")
+            }
+        }
+    }
+}
+
+/// The result of submitting an order to an orderbook
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OrderOutcome {
+    pub order_id: String,
+    /// Quantity matched into pending trades
+    pub filled: Amount,
+    /// Quantity left resting on the book
+    pub resting: Amount,
+    /// Quantity discarded because the order could not rest, or was killed
+    pub discarded: Amount,
+    /// The pending trades created by this order
+    pub trades: Vec<PendingTrade>,
+    /// The IDs of resting orders cancelled by self-trade prevention
+    pub cancelled: Vec<String>,
+    /// The outcomes of any stop orders activated by this order's trades
+    pub triggered: Vec<OrderOutcome>,
+}
+
+/// A replacement price and/or quantity for a resting order
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OrderAmendment {
+    pub price: Option<Amount>,
+    pub quantity: Option<Amount>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct PendingTrade {
-    pub bid_id: String,
-    pub ask_id: String,
-    pub quantity: f64,
-    pub price: f64,
-    pub created_at: String,
-    pub druid: String,
-}
+/// One side of an orderbook. Resting orders are grouped into price levels, with each
+/// level a FIFO queue so that orders at the same price fill in arrival order.
+///
+/// Serializes as a flat list of orders in ascending price order, which is the same
+/// shape as the original `Vec<Order>` representation
+#[derive(Debug, Clone, Default)]
+pub struct BookSide {
+    levels: BTreeMap<Amount, VecDeque<Order>>,
+    index: HashMap<String, Amount>,
+}
+
+impl BookSide {
+    pub fn new() -> Self {
+        BookSide {
+            levels: BTreeMap::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of resting orders on this side
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns true if there are no resting orders on this side
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Iterates over all resting orders in ascending price order, and arrival order
+    /// within each price level
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.levels.values().flatten()
+    }
+
+    /// Returns the lowest price on this side, if any
+    pub fn lowest_price(&self) -> Option<Amount> {
+        self.levels.keys().next().copied()
+    }
+
+    /// Returns the highest price on this side, if any
+    pub fn highest_price(&self) -> Option<Amount> {
+        self.levels.keys().next_back().copied()
+    }
+
+    /// Returns the first order in the queue at the lowest price
+    pub fn lowest(&self) -> Option<&Order> {
+        self.levels.values().next().and_then(|level| level.front())
+    }
+
+    /// Returns the first order in the queue at the highest price
+    pub fn highest(&self) -> Option<&Order> {
+        self.levels
+            .values()
+            .next_back()
+            .and_then(|level| level.front())
+    }
+
+    /// Returns true if an order with the given ID is resting on this side
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to look for
+    pub fn contains(&self, order_id: &str) -> bool {
+        self.index.contains_key(order_id)
+    }
+
+    /// Sums the unexpired quantity resting at prices the given order crosses, stopping
+    /// once `limit` has been reached. Orders with the same owner are not counted, and
+    /// counting stops at the first of them unless self-trade prevention cancels it
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The incoming order on the opposite side
+    /// * `limit` - The quantity at which to stop counting
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn crossing_quantity(&self, order: &Order, limit: Amount, now: i64) -> Amount {
+        let levels: Box<dyn Iterator<Item = (&Amount, &VecDeque<Order>)>> = if order.is_bid {
+            Box::new(self.levels.iter())
+        } else {
+            Box::new(self.levels.iter().rev())
+        };
+        let mut total = Amount::ZERO;
+
+        for (price, level) in levels {
+            if total >= limit || !order.crosses(*price) {
+                break;
+            }
+
+            for resting in level.iter().filter(|o| !o.is_expired(now)) {
+                if total >= limit {
+                    return total;
+                }
+
+                // Only cancelling the resting order lets matching carry on past it
+                if resting.same_owner(order) {
+                    match order.self_trade_prevention {
+                        SelfTradePrevention::CancelOldest => continue,
+                        _ => return total,
+                    }
+                }
+
+                total = total.saturating_add(resting.total_quantity());
+            }
+        }
+
+        total
+    }
+
+    /// Removes all orders that have expired, returning them
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn remove_expired(&mut self, now: i64) -> Vec<Order> {
+        let expired_ids: Vec<String> = self
+            .iter()
+            .filter(|o| o.is_expired(now))
+            .map(|o| o.id.clone())
+            .collect();
+
+        expired_ids
+            .iter()
+            .filter_map(|id| self.remove(id))
+            .collect()
+    }
+
+    /// Gets a resting order by its ID
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to retrieve
+    pub fn get(&self, order_id: &str) -> Option<&Order> {
+        let price = self.index.get(order_id)?;
+        self.levels.get(price)?.iter().find(|o| o.id == order_id)
+    }
+
+    /// Gets a mutable reference to a resting order by its ID. The order's price
+    /// must not be changed through this reference
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to retrieve
+    fn get_mut(&mut self, order_id: &str) -> Option<&mut Order> {
+        let price = self.index.get(order_id)?;
+        self.levels
+            .get_mut(price)?
+            .iter_mut()
+            .find(|o| o.id == order_id)
+    }
+
+    /// Adds an order to the back of the queue at its price
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to be inserted
+    pub fn insert(&mut self, order: Order) {
+        self.index.insert(order.id.clone(), order.price);
+        self.levels.entry(order.price).or_default().push_back(order);
+    }
+
+    /// Adds an order to the queue at its price behind any orders that arrived before it,
+    /// so that an order returning to the book regains its original time priority
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to be inserted
+    pub fn insert_by_priority(&mut self, order: Order) {
+        self.index.insert(order.id.clone(), order.price);
+
+        let level = self.levels.entry(order.price).or_default();
+        let idx = level
+            .iter()
+            .position(|o| o.sequence > order.sequence)
+            .unwrap_or(level.len());
+        level.insert(idx, order);
+    }
+
+    /// Removes a resting order by its ID, returning it if it was found
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to be removed
+    pub fn remove(&mut self, order_id: &str) -> Option<Order> {
+        let price = self.index.remove(order_id)?;
+        let level = self.levels.get_mut(&price)?;
+        let idx = level.iter().position(|o| o.id == order_id)?;
+        let order = level.remove(idx);
+
+        if level.is_empty() {
+            self.levels.remove(&price);
+        }
+
+        order
+    }
+
+    /// Gets a mutable reference to the first order in the queue at a price
+    ///
+    /// ### Arguments
+    ///
+    /// * `price` - The price level to look in
+    fn front_mut(&mut self, price: &Amount) -> Option<&mut Order> {
+        self.levels.get_mut(price)?.front_mut()
+    }
+
+    /// Removes the first order in the queue at a price
+    ///
+    /// ### Arguments
+    ///
+    /// * `price` - The price level to pop from
+    fn pop_front(&mut self, price: &Amount) -> Option<Order> {
+        let level = self.levels.get_mut(price)?;
+        let order = level.pop_front()?;
+
+        if level.is_empty() {
+            self.levels.remove(price);
+        }
+
+        self.index.remove(&order.id);
+        Some(order)
+    }
+}
+
+impl Serialize for BookSide {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for BookSide {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let orders = Vec::<Order>::deserialize(deserializer)?;
+        Ok(orders.into_iter().collect())
+    }
+}
+
+impl FromIterator<Order> for BookSide {
+    fn from_iter<I: IntoIterator<Item = Order>>(iter: I) -> Self {
+        let mut side = BookSide::new();
+        iter.into_iter().for_each(|order| side.insert(order));
+        side
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OrderBook {
+    pub bids: BookSide,
+    pub asks: BookSide,
+    pub pending_trades: Vec<PendingTrade>,
+    /// Stop and stop-limit orders waiting for a trade to reach their trigger price
+    #[serde(default)]
+    pub triggers: Vec<Order>,
+    #[serde(default)]
+    pub last_trade_price: Option<Amount>,
+    /// The most recent trades to leave the pending list, having been confirmed, failed
+    /// or expired. Only the last `MAX_SETTLED_TRADES` are kept
+    #[serde(default)]
+    pub settled_trades: Vec<PendingTrade>,
+    /// The fees earned on confirmed trades pruned from `settled_trades`
+    #[serde(default)]
+    pub pruned_fees: FeeTotals,
+    /// Fully matched orders kept off the book until their reserved quantity settles
+    #[serde(default)]
+    pub held: Vec<Order>,
+    /// The last time priority handed out to an order
+    #[serde(default)]
+    pub last_sequence: u64,
+    /// Barter orders waiting for a reciprocal order, oldest first
+    #[serde(default)]
+    pub barters: Vec<Order>,
+    /// The fee rates charged on trades as they are matched
+    #[serde(default)]
+    pub fees: FeeRates,
+    /// The number of decimal places a base unit of the listing represents
+    #[serde(default)]
+    pub decimals: u32,
+    /// The price increment of the listing, which post-only orders are repriced by
+    #[serde(default)]
+    pub tick_size: Amount,
+    /// The offset of the next event to be written to this orderbook's journal
+    #[serde(default)]
+    pub journal_offset: u64,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        OrderBook {
+            bids: BookSide::new(),
+            asks: BookSide::new(),
+            pending_trades: Vec::new(),
+            triggers: Vec::new(),
+            last_trade_price: None,
+            settled_trades: Vec::new(),
+            pruned_fees: FeeTotals::default(),
+            held: Vec::new(),
+            last_sequence: 0,
+            barters: Vec::new(),
+            fees: FeeRates::default(),
+            decimals: 0,
+            tick_size: Amount::ZERO,
+            journal_offset: 0,
+        }
+    }
+
+    /// Hands out the next time priority
+    fn next_sequence(&mut self) -> u64 {
+        self.last_sequence += 1;
+        self.last_sequence
+    }
+
+    /// Returns the highest priority bid, i.e. the earliest order at the highest price
+    pub fn best_bid(&self) -> Option<&Order> {
+        self.bids.highest()
+    }
+
+    /// Returns the highest priority ask, i.e. the earliest order at the lowest price
+    pub fn best_ask(&self) -> Option<&Order> {
+        self.asks.lowest()
+    }
+
+    /// Returns the best price on the opposite side of the book to an order, if any
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to find the best opposite price for
+    pub fn best_opposite_price(&self, order: &Order) -> Option<Amount> {
+        if order.is_bid {
+            self.asks.lowest_price()
+        } else {
+            self.bids.highest_price()
+        }
+    }
+
+    /// Returns true if an order would match against the book on arrival
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to check
+    pub fn would_cross(&self, order: &Order) -> bool {
+        self.best_opposite_price(order)
+            .map(|price| order.crosses(price))
+            .unwrap_or(false)
+    }
+
+    /// Applies an order's post-only handling before it is matched, either rejecting it
+    /// or moving its price one tick behind the best opposite quote if it would cross
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to check
+    fn apply_post_only(&self, order: &mut Order) -> Result<(), OrderBookError> {
+        if order.post_only == PostOnly::Disabled || !self.would_cross(order) {
+            return Ok(());
+        }
+
+        // Orderbooks stored without a tick size reprice by a single base unit
+        let tick = self.tick_size.max(Amount::new(1));
+        let repriced = match (order.post_only, order.order_type) {
+            (PostOnly::Reprice, OrderType::Limit) => {
+                self.best_opposite_price(order)
+                    .and_then(|best| match order.is_bid {
+                        true => best.checked_sub(tick),
+                        false => best.checked_add(tick),
+                    })
+            }
+            _ => None,
+        };
+
+        match repriced {
+            Some(price) => {
+                order.price = price;
+                Ok(())
+            }
+            None => Err(OrderBookError::PostOnlyWouldCross(order.id.clone())),
+        }
+    }
+
+    /// Matches an order against the book as of the current time. See `add_order_at`
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to be matched
+    pub fn add_order(&mut self, order: &mut Order) -> Result<OrderOutcome, OrderBookError> {
+        self.add_order_at(order, Utc::now().timestamp())
+    }
+
+    /// Adds an order to the book as of the given time. Stop orders wait in the trigger
+    /// book unless the last trade price has already reached their trigger, and all
+    /// other orders are matched through `match_order`. Any stop orders activated by
+    /// the resulting trades are then matched in turn. Barter orders are only matched
+    /// against this book's barter list, through `match_barter`
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to be added
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn add_order_at(
+        &mut self,
+        order: &mut Order,
+        now: i64,
+    ) -> Result<OrderOutcome, OrderBookError> {
+        if order.is_barter() {
+            return Ok(self.match_barter(None, order, now));
+        }
+
+        let already_triggered = self
+            .last_trade_price
+            .map(|price| order.is_triggered_by(price))
+            .unwrap_or(false);
+
+        if order.is_stop() && !already_triggered {
+            self.triggers.push(order.clone());
+            return Ok(OrderOutcome {
+                order_id: order.id.clone(),
+                resting: order.quantity,
+                ..Default::default()
+            });
+        }
+
+        order.activate();
+        let mut outcome = self.match_order(order, now)?;
+        let trade_prices = outcome.trades.iter().map(|t| t.price).collect();
+        outcome.triggered = self.activate_triggers(trade_prices, now);
+
+        Ok(outcome)
+    }
+
+    /// Activates the stop orders reached by the given trade prices and feeds them through
+    /// the normal matching path, repeating for any trades the activated orders create
+    ///
+    /// ### Arguments
+    ///
+    /// * `trade_prices` - The prices of newly created trades
+    /// * `now` - The current Unix timestamp in seconds
+    fn activate_triggers(&mut self, mut trade_prices: Vec<Amount>, now: i64) -> Vec<OrderOutcome> {
+        let mut outcomes = Vec::new();
+
+        while !trade_prices.is_empty() {
+            let (triggered, waiting): (Vec<Order>, Vec<Order>) = std::mem::take(&mut self.triggers)
+                .into_iter()
+                .partition(|o| trade_prices.iter().any(|p| o.is_triggered_by(*p)));
+            self.triggers = waiting;
+            trade_prices.clear();
+
+            for mut order in triggered {
+                if order.is_expired(now) {
+                    continue;
+                }
+
+                // A triggered post-only order that would cross is dropped like any other
+                order.activate();
+                if let Ok(outcome) = self.match_order(&mut order, now) {
+                    trade_prices.extend(outcome.trades.iter().map(|t| t.price));
+                    outcomes.push(outcome);
+                }
+            }
+        }
+
+        outcomes
+    }
+
+    /// Matches an order against the opposite side of the book in price-time priority,
+    /// best price first and oldest order first within a price. Expired counter-orders
+    /// are removed from the book instead of being matched. Once the book no longer
+    /// crosses, any remainder rests on the book if the order's type and time-in-force
+    /// allow it, and is discarded otherwise. Post-only orders that would match are
+    /// rejected or repriced before matching
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to be matched
+    /// * `now` - The current Unix timestamp in seconds
+    fn match_order(&mut self, order: &mut Order, now: i64) -> Result<OrderOutcome, OrderBookError> {
+        self.apply_post_only(order)?;
+        order.sequence = self.next_sequence();
+
+        Ok(self.sweep(order, now))
+    }
+
+    /// Matches an order against the opposite side of the book as `match_order` does, but
+    /// without checking it is post-only or giving it a new time priority. Any remainder
+    /// that rests joins its price level behind the orders that arrived before it
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The order to be matched
+    /// * `now` - The current Unix timestamp in seconds
+    fn sweep(&mut self, order: &mut Order, now: i64) -> OrderOutcome {
+        let (own_side, match_side) = if order.is_bid {
+            (&mut self.bids, &mut self.asks)
+        } else {
+            (&mut self.asks, &mut self.bids)
+        };
+        let mut outcome = OrderOutcome {
+            order_id: order.id.clone(),
+            ..Default::default()
+        };
+
+        // An incoming iceberg order matches with its full size, and only hides its
+        // reserve again if it rests
+        order.quantity = order.total_quantity();
+        order.reserve_quantity = Amount::ZERO;
+
+        // Orders that have already expired, and fill-or-kill orders the book can't fill
+        // completely, are rejected outright
+        if order.is_expired(now)
+            || (order.time_in_force == TimeInForce::Fok
+                && match_side.crossing_quantity(order, order.quantity, now) < order.quantity)
+        {
+            outcome.discarded = order.quantity;
+            order.quantity = Amount::ZERO;
+            return outcome;
+        }
+
+        while !order.quantity.is_zero() {
+            // The best opposite price is the lowest ask for a bid, and the highest bid for an ask
+            let best_price = if order.is_bid {
+                match_side.lowest_price()
+            } else {
+                match_side.highest_price()
+            };
+
+            let match_price = match best_price {
+                Some(price) if order.crosses(price) => price,
+                _ => break,
+            };
+
+            let match_order = match match_side.front_mut(&match_price) {
+                Some(match_order) => match_order,
+                None => break,
+            };
+
+            if match_order.is_expired(now) {
+                match_side.pop_front(&match_price);
+                continue;
+            }
+
+            // Wash trades are prevented according to the incoming order's mode, and
+            // never produce a pending trade
+            if match_order.same_owner(order) {
+                let cancel_newest = match order.self_trade_prevention {
+                    SelfTradePrevention::CancelNewest => true,
+                    SelfTradePrevention::CancelOldest | SelfTradePrevention::CancelBoth => {
+                        if let Some(cancelled) = match_side.pop_front(&match_price) {
+                            outcome.cancelled.push(cancelled.id.clone());
+                            hold_reserved(&mut self.held, cancelled);
+                        }
+                        order.self_trade_prevention == SelfTradePrevention::CancelBoth
+                    }
+                    SelfTradePrevention::Decrement => {
+                        let quantity = match_order.total_quantity().min(order.quantity);
+                        let resting_left = match_order.total_quantity() - quantity;
+                        match_order.reserve_quantity = Amount::ZERO;
+                        match_order.quantity = resting_left;
+                        match_order.hide_reserve();
+
+                        if resting_left.is_zero() {
+                            if let Some(cancelled) = match_side.pop_front(&match_price) {
+                                outcome.cancelled.push(cancelled.id.clone());
+                                hold_reserved(&mut self.held, cancelled);
+                            }
+                        }
+
+                        order.quantity -= quantity;
+                        outcome.discarded += quantity;
+                        false
+                    }
+                };
+
+                if cancel_newest {
+                    outcome.discarded += order.quantity;
+                    order.quantity = Amount::ZERO;
+                }
+                continue;
+            }
+
+            let quantity = match_order.quantity.min(order.quantity);
+            let (bid_id, ask_id) = if order.is_bid {
+                (order.id.clone(), match_order.id.clone())
+            } else {
+                (match_order.id.clone(), order.id.clone())
+            };
+            let price = match order.order_type {
+                OrderType::Market | OrderType::Stop => match_order.price,
+                OrderType::Limit | OrderType::StopLimit => match_order.price.min(order.price),
+            };
+
+            let (maker_fee, taker_fee) = self.fees.charge(price, quantity, self.decimals);
+            let pending_trade = PendingTrade {
+                bid_id,
+                ask_id,
+                quantity,
+                price,
+                created_at: format_timestamp(now),
+                druid: construct_druid(),
+                status: TradeStatus::Pending,
+                expires_at: Some(now.saturating_add(PENDING_TRADE_TIMEOUT)),
+                maker_is_bid: match_order.is_bid,
+                legs: Vec::new(),
+                maker_fee,
+                taker_fee,
+            };
+            self.last_trade_price = Some(price);
+            self.pending_trades.push(pending_trade.clone());
+            outcome.trades.push(pending_trade);
+            outcome.filled += quantity;
+
+            // Matched quantity stays reserved against both orders until the trade settles
+            match_order.quantity -= quantity;
+            match_order.reserved_quantity += quantity;
+            order.quantity -= quantity;
+            order.reserved_quantity += quantity;
+
+            // Fully filled orders leave the front of their queue. Iceberg orders with
+            // reserve left are refilled and rejoin at the back, losing time priority,
+            // while others are held until their reserved quantity settles
+            if match_order.quantity.is_zero() {
+                if let Some(mut filled) = match_side.pop_front(&match_price) {
+                    if !filled.reserve_quantity.is_zero() {
+                        self.last_sequence += 1;
+                        filled.sequence = self.last_sequence;
+                        filled.replenish();
+                        match_side.insert(filled);
+                    } else {
+                        self.held.push(filled);
+                    }
+                }
+            }
+        }
+
+        if !order.quantity.is_zero() && order.can_rest() {
+            outcome.resting = order.quantity;
+            let mut resting = order.clone();
+            resting.hide_reserve();
+            own_side.insert_by_priority(resting);
+            return outcome;
+        }
+
+        outcome.discarded += order.quantity;
+        if !order.reserved_quantity.is_zero() {
+            self.held.push(Order {
+                quantity: Amount::ZERO,
+                ..order.clone()
+            });
+        }
+
+        outcome
+    }
+
+    /// Matches a barter order against the first reciprocal barter order in this book or
+    /// in the book of the listing it wants, producing a single pending trade with a leg
+    /// for each asset delivered. Each order delivers exactly what its counterparty wants,
+    /// and only that part of it is held until the trade settles. Any part of an order
+    /// left over waits for another reciprocal order, at the same rate, if its
+    /// time-in-force allows it. The trade is recorded in every book involved. Without a
+    /// match the order waits in this book's barter list if its time-in-force allows it
+    ///
+    /// ### Arguments
+    ///
+    /// * `counter` - The orderbook of the listing the order wants, if it is stored
+    /// * `order` - The barter order to be matched
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn match_barter(
+        &mut self,
+        mut counter: Option<&mut OrderBook>,
+        order: &mut Order,
+        now: i64,
+    ) -> OrderOutcome {
+        order.sequence = self.next_sequence();
+        let mut outcome = OrderOutcome {
+            order_id: order.id.clone(),
+            ..Default::default()
+        };
+
+        if order.is_expired(now) {
+            outcome.discarded = order.quantity;
+            order.quantity = Amount::ZERO;
+            return outcome;
+        }
+
+        let counter_match = counter
+            .as_deref_mut()
+            .and_then(|counter| counter.take_reciprocal(order, now));
+        let in_counter = counter_match.is_some();
+
+        let matched = match counter_match.or_else(|| self.take_reciprocal(order, now)) {
+            Some(matched) => matched,
+            None if order.can_rest() => {
+                outcome.resting = order.quantity;
+                self.barters.push(order.clone());
+                return outcome;
+            }
+            None => {
+                outcome.discarded = order.quantity;
+                order.quantity = Amount::ZERO;
+                return outcome;
+            }
+        };
+
+        let (swapped, remainder) = order.split_barter(&matched);
+        let pending_trade = PendingTrade::barter(&[order.clone(), matched], now);
+
+        if let Some(counter) = counter.filter(|_| in_counter) {
+            counter.pending_trades.push(pending_trade.clone());
+        }
+        self.pending_trades.push(pending_trade.clone());
+
+        outcome.filled = swapped.quantity;
+        outcome.trades.push(pending_trade);
+        order.reserved_quantity += swapped.quantity;
+        order.quantity = Amount::ZERO;
+        self.held.push(Order {
+            quantity: Amount::ZERO,
+            reserved_quantity: order.reserved_quantity,
+            ..swapped
+        });
+
+        // Whatever the swap didn't deliver waits for another reciprocal order
+        match remainder {
+            Some(remainder) if remainder.can_rest() => {
+                outcome.resting = remainder.quantity;
+                order.quantity = remainder.quantity;
+                self.barters.push(remainder);
+            }
+            Some(remainder) => outcome.discarded = remainder.quantity,
+            None => (),
+        }
+
+        outcome
+    }
+
+    /// Takes the oldest barter order reciprocal to the given order, holding the part
+    /// of it the swap delivers. Returns the order as it was before being held
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The incoming barter order
+    /// * `now` - The current Unix timestamp in seconds
+    fn take_reciprocal(&mut self, order: &Order, now: i64) -> Option<Order> {
+        let id = self
+            .barters
+            .iter()
+            .find(|o| !o.is_expired(now) && order.is_reciprocal(o))?
+            .id
+            .clone();
+
+        self.hold_barter(&id, order)
+    }
+
+    /// Holds the part of a waiting barter order that a swap delivers to `to`, leaving
+    /// any part left over waiting in its place. Returns the order as it was before
+    /// being held
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the barter order
+    /// * `to` - The barter order it delivers to in the swap
+    pub fn hold_barter(&mut self, order_id: &str, to: &Order) -> Option<Order> {
+        let idx = self.barters.iter().position(|o| o.id == order_id)?;
+        let matched = self.barters[idx].clone();
+        let (swapped, remainder) = matched.split_barter(to);
+
+        match remainder {
+            Some(remainder) => self.barters[idx] = remainder,
+            None => {
+                self.barters.remove(idx);
+            }
+        }
+        self.held.push(Order {
+            quantity: Amount::ZERO,
+            reserved_quantity: swapped.reserved_quantity + swapped.quantity,
+            ..swapped
+        });
+
+        Some(matched)
+    }
+
+    /// Places a barter order in this book's barter list without matching it, giving it
+    /// the time priority `match_barter` would have. Used when replaying a journal, where
+    /// any swap the order took part in is replayed separately
+    ///
+    /// ### Arguments
+    ///
+    /// * `order` - The barter order to place
+    /// * `now` - The Unix timestamp in seconds the order arrived at
+    pub fn queue_barter(&mut self, order: &mut Order, now: i64) {
+        order.sequence = self.next_sequence();
+
+        if !order.is_expired(now) && order.can_rest() {
+            self.barters.push(order.clone());
+        }
+    }
+
+    /// Returns a copy of this orderbook as shown publicly, with the hidden reserve of
+    /// any iceberg orders removed, whether resting, waiting for a trigger or bartering
+    pub fn public_view(&self) -> OrderBook {
+        OrderBook {
+            bids: self.bids.iter().map(Order::displayed).collect(),
+            asks: self.asks.iter().map(Order::displayed).collect(),
+            triggers: self.triggers.iter().map(Order::displayed).collect(),
+            barters: self.barters.iter().map(Order::displayed).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Removes all expired orders from both sides of the book, returning them
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn purge_expired(&mut self, now: i64) -> Vec<Order> {
+        let mut expired = self.bids.remove_expired(now);
+        expired.extend(self.asks.remove_expired(now));
+
+        let (trigger_expired, waiting): (Vec<Order>, Vec<Order>) =
+            std::mem::take(&mut self.triggers)
+                .into_iter()
+                .partition(|o| o.is_expired(now));
+        self.triggers = waiting;
+        expired.extend(trigger_expired);
+
+        let (barter_expired, waiting): (Vec<Order>, Vec<Order>) = std::mem::take(&mut self.barters)
+            .into_iter()
+            .partition(|o| o.is_expired(now));
+        self.barters = waiting;
+        expired.extend(barter_expired);
+
+        expired
+    }
+
+    /// Removes a resting, held or waiting stop order from the order book, returning it
+    /// if it was found. Any quantity it has reserved is not returned if its trades fail
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to be cancelled
+    pub fn cancel_order(&mut self, order_id: &str) -> Option<Order> {
+        if let Some(order) = self.bids.remove(order_id) {
+            return Some(order);
+        }
+
+        if let Some(order) = self.asks.remove(order_id) {
+            return Some(order);
+        }
+
+        // A barter order partly held against a swap has its waiting remainder cancelled
+        if let Some(idx) = self.barters.iter().position(|o| o.id == order_id) {
+            return Some(self.barters.remove(idx));
+        }
+
+        if let Some(idx) = self.held.iter().position(|o| o.id == order_id) {
+            return Some(self.held.remove(idx));
+        }
+
+        let idx = self.triggers.iter().position(|o| o.id == order_id)?;
+        Some(self.triggers.remove(idx))
+    }
+
+    /// Moves a pending trade to a new settlement state, returning the updated trade.
+    ///
+    /// Trades that reach a final state leave the pending list and release the quantity
+    /// reserved against their orders. A confirmed trade consumes it, while a failed or
+    /// expired trade returns it to the resting order it was matched against, which keeps
+    /// its original time priority. The incoming order's side of the trade is dropped, as
+    /// it was only ever an instruction to trade against that liquidity
+    ///
+    /// ### Arguments
+    ///
+    /// * `druid` - The DRUID of the trade
+    /// * `status` - The state to move the trade to
+    pub fn update_trade_status(
+        &mut self,
+        druid: &str,
+        status: TradeStatus,
+    ) -> Result<PendingTrade, OrderBookError> {
+        self.update_trade_status_at(druid, status, Utc::now().timestamp())
+    }
+
+    /// Moves a pending trade to a new settlement state as of the given time. See
+    /// `update_trade_status`
+    ///
+    /// ### Arguments
+    ///
+    /// * `druid` - The DRUID of the trade
+    /// * `status` - The state to move the trade to
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn update_trade_status_at(
+        &mut self,
+        druid: &str,
+        status: TradeStatus,
+        now: i64,
+    ) -> Result<PendingTrade, OrderBookError> {
+        let idx = self
+            .pending_trades
+            .iter()
+            .position(|t| t.druid == druid)
+            .ok_or_else(|| OrderBookError::TradeNotFound(druid.to_string()))?;
+
+        let current = self.pending_trades[idx].status;
+        if !current.can_transition_to(status) {
+            return Err(OrderBookError::InvalidTradeTransition(current, status));
+        }
+
+        self.pending_trades[idx].status = status;
+        if !status.is_final() {
+            return Ok(self.pending_trades[idx].clone());
+        }
+
+        let trade = self.pending_trades.remove(idx);
+        let restore = status != TradeStatus::Confirmed;
+
+        // Both sides of a failed swap go back to waiting, as neither took the other's liquidity
+        if !trade.legs.is_empty() {
+            for leg in &trade.legs {
+                self.release_barter(&leg.order_id, leg.quantity, restore);
+            }
+            self.record_settled(trade.clone());
+            return Ok(trade);
+        }
+
+        // Quantity goes back to both orders, the maker's first so it keeps its place
+        // ahead of anything the taker's returned quantity now crosses
+        let (bid, ask) = ((&trade.bid_id, true), (&trade.ask_id, false));
+        let sides = match trade.maker_is_bid {
+            true => [bid, ask],
+            false => [ask, bid],
+        };
+        for (order_id, is_bid) in sides {
+            self.release_reservation(order_id, is_bid, trade.quantity, restore, now);
+        }
+        self.record_settled(trade.clone());
+
+        Ok(trade)
+    }
+
+    /// Records a trade that has left the pending list. Only the most recent settled
+    /// trades are kept, with the fees of older ones folded into `pruned_fees` so the
+    /// orderbook doesn't grow with every trade ever made
+    ///
+    /// ### Arguments
+    ///
+    /// * `trade` - The settled trade
+    fn record_settled(&mut self, trade: PendingTrade) {
+        self.settled_trades.push(trade);
+
+        let excess = self.settled_trades.len().saturating_sub(MAX_SETTLED_TRADES);
+        for pruned in self.settled_trades.drain(..excess) {
+            self.pruned_fees.add(&pruned);
+        }
+    }
+
+    /// Expires every pending trade whose settlement deadline has passed, returning
+    /// their matched quantity to the book
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn expire_trades(&mut self, now: i64) -> Vec<PendingTrade> {
+        let expired_druids: Vec<String> = self
+            .pending_trades
+            .iter()
+            .filter(|t| t.status.can_transition_to(TradeStatus::Expired))
+            .filter(|t| t.expires_at.map(|expiry| expiry <= now).unwrap_or(false))
+            .map(|t| t.druid.clone())
+            .collect();
+
+        expired_druids
+            .iter()
+            .filter_map(|druid| {
+                self.update_trade_status_at(druid, TradeStatus::Expired, now)
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Releases quantity reserved against one side of a settling trade. When `restore`
+    /// is set the quantity is returned to the order, which rejoins the book at its
+    /// original priority if it had been held, and is matched again if it now crosses
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order
+    /// * `is_bid` - Whether the order is a bid
+    /// * `quantity` - The quantity to release
+    /// * `restore` - Whether to return the quantity to the order
+    /// * `now` - The current Unix timestamp in seconds
+    fn release_reservation(
+        &mut self,
+        order_id: &str,
+        is_bid: bool,
+        quantity: Amount,
+        restore: bool,
+        now: i64,
+    ) {
+        let side = if is_bid {
+            &mut self.bids
+        } else {
+            &mut self.asks
+        };
+
+        if let Some(order) = side.get_mut(order_id) {
+            order.reserved_quantity = order.reserved_quantity.saturating_sub(quantity);
+            if restore {
+                order.quantity += quantity;
+                order.hide_reserve();
+            }
+        } else if let Some(idx) = self
+            .held
+            .iter()
+            .position(|o| o.id == order_id && o.is_bid == is_bid)
+        {
+            let order = &mut self.held[idx];
+            order.reserved_quantity = order.reserved_quantity.saturating_sub(quantity);
+
+            if restore && order.can_rest() {
+                let mut order = self.held.remove(idx);
+                order.quantity += quantity;
+                order.hide_reserve();
+                side.insert_by_priority(order);
+            } else if order.reserved_quantity.is_zero() {
+                self.held.remove(idx);
+            }
+        } else {
+            return;
+        }
+
+        // Returned quantity may now cross orders that arrived while it was reserved
+        let side = if is_bid { &self.bids } else { &self.asks };
+        let crossing = side
+            .get(order_id)
+            .filter(|order| self.would_cross(order))
+            .is_some();
+
+        if crossing {
+            let removed = if is_bid {
+                self.bids.remove(order_id)
+            } else {
+                self.asks.remove(order_id)
+            };
+
+            // The order is matched with its original priority, and any stops its trades
+            // reach are activated. A post-only order that would now cross keeps its place
+            if let Some(mut order) = removed {
+                if self.apply_post_only(&mut order).is_err() {
+                    if is_bid {
+                        self.bids.insert_by_priority(order);
+                    } else {
+                        self.asks.insert_by_priority(order);
+                    }
+                    return;
+                }
+
+                let outcome = self.sweep(&mut order, now);
+                let trade_prices = outcome.trades.iter().map(|t| t.price).collect();
+                self.activate_triggers(trade_prices, now);
+            }
+        }
+    }
+
+    /// Releases a barter order held against a settling swap. When `restore` is set the
+    /// order rejoins this book's barter list at its original priority
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the barter order
+    /// * `delivered` - The quantity the order delivers in the swap
+    /// * `restore` - Whether to return the order to the barter list
+    fn release_barter(&mut self, order_id: &str, delivered: Amount, restore: bool) {
+        // An order swapped in parts has a held part for each swap, told apart by what it delivers
+        let held = |o: &&Order| o.id == order_id && o.is_barter();
+        let idx = match self
+            .held
+            .iter()
+            .position(|o| held(&o) && !o.is_bid && o.reserved_quantity == delivered)
+            .or_else(|| self.held.iter().position(|o| held(&o)))
+        {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        let mut order = self.held.remove(idx);
+        if restore && order.can_rest() {
+            order.quantity += order.reserved_quantity;
+            order.reserved_quantity = Amount::ZERO;
+
+            // A remainder still waiting is made whole again
+            if let Some(waiting) = self.barters.iter_mut().find(|o| o.id == order_id) {
+                waiting.quantity += order.quantity;
+                waiting.price += order.price;
+                return;
+            }
+
+            let idx = self
+                .barters
+                .iter()
+                .position(|o| o.sequence > order.sequence)
+                .unwrap_or(self.barters.len());
+            self.barters.insert(idx, order);
+        }
+    }
+
+    /// Amends the price and/or quantity of a resting order, returning the amended order.
+    ///
+    /// A quantity decrease at the same price is applied in place and keeps the order's
+    /// time priority. A price change or quantity increase loses priority, and the order
+    /// is resubmitted through `add_order_at` so it can match against the opposite side
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to be amended
+    /// * `amendment` - The new price and/or quantity for the order
+    pub fn amend_order(
+        &mut self,
+        order_id: &str,
+        amendment: OrderAmendment,
+    ) -> Result<Order, OrderBookError> {
+        self.amend_order_at(order_id, amendment, Utc::now().timestamp())
+    }
+
+    /// Amends a resting order as of the given time. See `amend_order`
+    ///
+    /// ### Arguments
+    ///
+    /// * `order_id` - The ID of the order to be amended
+    /// * `amendment` - The new price and/or quantity for the order
+    /// * `now` - The current Unix timestamp in seconds
+    pub fn amend_order_at(
+        &mut self,
+        order_id: &str,
+        amendment: OrderAmendment,
+        now: i64,
+    ) -> Result<Order, OrderBookError> {
+        let not_found = || OrderBookError::OrderNotFound(order_id.to_string());
+        let order_list = if self.bids.contains(order_id) {
+            &self.bids
+        } else {
+            &self.asks
+        };
+
+        let mut order = order_list.get(order_id).ok_or_else(not_found)?.clone();
+        let price = amendment.price.unwrap_or(order.price);
+        let quantity = amendment.quantity.unwrap_or(order.total_quantity());
+
+        // Quantity decreases at the same price keep their place in the queue. For
+        // icebergs the amended quantity covers the reserve as well as the displayed slice
+        if price == order.price && quantity <= order.total_quantity() {
+            let order_list = if order.is_bid {
+                &mut self.bids
+            } else {
+                &mut self.asks
+            };
+
+            if quantity.is_zero() {
+                return order_list.remove(order_id).ok_or_else(not_found);
+            }
+
+            let current = order_list.get_mut(order_id).ok_or_else(not_found)?;
+            current.quantity = quantity;
+            current.reserve_quantity = Amount::ZERO;
+            current.hide_reserve();
+            return Ok(current.clone());
+        }
+
+        // Check the replacement before pulling the original, so a post-only amendment
+        // that can't be placed or repriced leaves the resting order untouched. Pulling
+        // the original doesn't change the opposite side, so the check still holds after
+        order.price = price;
+        order.quantity = quantity;
+        order.reserve_quantity = Amount::ZERO;
+        self.apply_post_only(&mut order)?;
+
+        let original = self.cancel_order(order_id).ok_or_else(not_found)?;
+        if let Err(e) = self.add_order_at(&mut order, now) {
+            let order_list = if original.is_bid {
+                &mut self.bids
+            } else {
+                &mut self.asks
+            };
+            order_list.insert_by_priority(original);
+            return Err(e);
+        }
+
+        Ok(order)
+    }
+}
+
+/// Holds an order removed from the book while quantity is still reserved against it,
+/// so a failed trade can give that quantity back
+///
+/// ### Arguments
+///
+/// * `held` - The orders held against pending trades
+/// * `order` - The removed order
+fn hold_reserved(held: &mut Vec<Order>, order: Order) {
+    if !order.reserved_quantity.is_zero() {
+        held.push(Order {
+            quantity: Amount::ZERO,
+            reserve_quantity: Amount::ZERO,
+            ..order
+        });
+    }
+}
+
+/// Formats a Unix timestamp in seconds as a UTC date and time
+///
+/// ### Arguments
+///
+/// * `now` - The Unix timestamp in seconds
+fn format_timestamp(now: i64) -> String {
+    Utc.timestamp_opt(now, 0)
+        .single()
+        .map(|t| t.to_string())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Asset {
+    pub address: String,
+    pub name: String,
+    pub symbol: Option<String>,
+    pub total_supply: u64,
+    pub highest_bid: Option<String>,
+    pub lowest_ask: Option<String>,
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+#[allow(clippy::len_zero)]
+mod tests {
+    use super::*;
+
+    fn create_simple_bid(price: u64, quantity: u64) -> Order {
+        Order {
+            id: String::from("1"),
+            listing_id: String::from("1"),
+            price: Amount::new(price),
+            quantity: Amount::new(quantity),
+            is_bid: true,
+            created_at: String::from(""),
+            druid: None,
+            desired_listing_id: None,
+            ..Default::default()
+        }
+    }
+
+    fn create_simple_ask(price: u64, quantity: u64) -> Order {
+        Order {
+            id: String::from("1"),
+            listing_id: String::from("1"),
+            price: Amount::new(price),
+            quantity: Amount::new(quantity),
+            is_bid: false,
+            created_at: String::from(""),
+            druid: None,
+            desired_listing_id: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_add_first_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut order = create_simple_bid(1, 1);
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut order).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.best_bid().unwrap().price, Amount::new(1));
+        assert_eq!(order_book.best_bid().unwrap().id, String::from("1"));
+    }
+
+    #[test]
+    fn should_match_bid_to_ask() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(15, 10);
+        let mut bid = create_simple_bid(20, 3);
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(order_book.bids.len(), 0);
+        assert_eq!(order_book.asks.len(), 1);
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(7));
+        assert_eq!(order_book.pending_trades.len(), 1);
+        assert_eq!(order_book.pending_trades[0].quantity, Amount::new(3));
+        assert_eq!(order_book.pending_trades[0].price, Amount::new(15));
+        assert_eq!(order_book.pending_trades[0].bid_id, String::from("1"));
+        assert_eq!(order_book.pending_trades[0].ask_id, String::from("1"));
+        assert!(order_book.pending_trades[0].druid.len() > 0);
+    }
+
+    #[test]
+    fn should_match_ask_to_bid() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(15, 10);
+        let mut ask = create_simple_ask(10, 3);
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut bid).unwrap();
+        order_book.add_order(&mut ask).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(7));
+        assert_eq!(order_book.asks.len(), 0);
+        assert_eq!(order_book.pending_trades.len(), 1);
+        assert_eq!(order_book.pending_trades[0].quantity, Amount::new(3));
+        assert_eq!(order_book.pending_trades[0].price, Amount::new(10));
+        assert_eq!(order_book.pending_trades[0].bid_id, String::from("1"));
+        assert_eq!(order_book.pending_trades[0].ask_id, String::from("1"));
+        assert!(order_book.pending_trades[0].druid.len() > 0);
+    }
+
+    #[test]
+    fn should_handle_unmatched_orders() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(15, 10);
+        let mut ask = create_simple_ask(20, 3);
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut bid).unwrap();
+        order_book.add_order(&mut ask).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(10));
+        assert_eq!(order_book.best_bid().unwrap().id, String::from("1"));
+        assert_eq!(order_book.asks.len(), 1);
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(3));
+        assert_eq!(order_book.best_ask().unwrap().id, String::from("1"));
+        assert_eq!(order_book.pending_trades.len(), 0);
+    }
+
+    #[test]
+    fn should_cancel_resting_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(15, 10);
+        let mut ask = create_simple_ask(20, 3);
+        ask.id = String::from("2");
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut bid).unwrap();
+        order_book.add_order(&mut ask).unwrap();
+        let cancelled = order_book.cancel_order("2");
+        let missing = order_book.cancel_order("3");
+
+        //
+        // Assert
+        //
+        assert_eq!(cancelled.unwrap().id, String::from("2"));
+        assert!(missing.is_none());
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.asks.len(), 0);
+    }
+
+    #[test]
+    fn should_amend_quantity_down_in_place() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(15, 10);
+        let amendment = OrderAmendment {
+            price: None,
+            quantity: Some(Amount::new(4)),
+        };
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut bid).unwrap();
+        let amended = order_book.amend_order("1", amendment);
+
+        //
+        // Assert
+        //
+        assert_eq!(amended.unwrap().quantity, Amount::new(4));
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(4));
+        assert_eq!(order_book.pending_trades.len(), 0);
+    }
+
+    #[test]
+    fn should_rematch_on_price_amendment() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(15, 10);
+        let mut ask = create_simple_ask(20, 3);
+        ask.id = String::from("2");
+        let amendment = OrderAmendment {
+            price: Some(Amount::new(15)),
+            quantity: None,
+        };
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut bid).unwrap();
+        order_book.add_order(&mut ask).unwrap();
+        let amended = order_book.amend_order("2", amendment);
+
+        //
+        // Assert
+        //
+        assert_eq!(amended.unwrap().quantity, Amount::new(0));
+        assert_eq!(order_book.asks.len(), 0);
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(7));
+        assert_eq!(order_book.pending_trades.len(), 1);
+        assert_eq!(order_book.pending_trades[0].ask_id, String::from("2"));
+    }
+
+    #[test]
+    fn should_keep_order_when_post_only_amendment_cant_be_placed() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        order_book.tick_size = Amount::new(5);
+        let mut ask = create_simple_ask(3, 1);
+        let mut bid = create_simple_bid(2, 1);
+        bid.id = String::from("bid");
+        bid.post_only = PostOnly::Reprice;
+        let amendment = OrderAmendment {
+            price: Some(Amount::new(4)),
+            quantity: None,
+        };
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+        let amended = order_book.amend_order("bid", amendment);
+
+        //
+        // Assert
+        //
+        assert_eq!(
+            amended.unwrap_err(),
+            OrderBookError::PostOnlyWouldCross(String::from("bid"))
+        );
+        assert_eq!(order_book.best_bid().unwrap().id, String::from("bid"));
+        assert_eq!(order_book.best_bid().unwrap().price, Amount::new(2));
+    }
+
+    #[test]
+    fn should_fill_equal_prices_in_arrival_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut first = create_simple_ask(10, 2);
+        let mut second = create_simple_ask(10, 2);
+        let mut better = create_simple_ask(9, 1);
+        let mut bid = create_simple_bid(10, 4);
+        first.id = String::from("first");
+        second.id = String::from("second");
+        better.id = String::from("better");
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut first).unwrap();
+        order_book.add_order(&mut second).unwrap();
+        order_book.add_order(&mut better).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        let ask_ids: Vec<&str> = order_book
+            .pending_trades
+            .iter()
+            .map(|t| t.ask_id.as_str())
+            .collect();
+        assert_eq!(ask_ids, vec!["better", "first", "second"]);
+        assert_eq!(order_book.asks.len(), 1);
+        assert_eq!(order_book.best_ask().unwrap().id, String::from("second"));
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(1));
+    }
+
+    #[test]
+    fn should_rest_remainder_after_sweeping_book() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 2);
+        let mut bid = create_simple_bid(12, 5);
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(order_book.asks.len(), 0);
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(3));
+    }
+
+    #[test]
+    fn should_serialize_sides_as_price_ordered_lists() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut high = create_simple_bid(12, 1);
+        let mut low = create_simple_bid(8, 1);
+        high.id = String::from("high");
+        low.id = String::from("low");
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut high).unwrap();
+        order_book.add_order(&mut low).unwrap();
+        let document = mongodb::bson::to_document(&order_book).unwrap();
+        let restored: OrderBook = mongodb::bson::from_document(document.clone()).unwrap();
+
+        //
+        // Assert
+        //
+        let bids = document.get_array("bids").unwrap();
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].as_document().unwrap().get_str("id").unwrap(), "low");
+        assert_eq!(restored.bids.len(), 2);
+        assert_eq!(restored.best_bid().unwrap().id, String::from("high"));
+    }
+
+    #[test]
+    fn should_sweep_book_with_market_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut near = create_simple_ask(10, 2);
+        let mut far = create_simple_ask(50, 2);
+        let mut bid = create_simple_bid(0, 5);
+        bid.order_type = OrderType::Market;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut near).unwrap();
+        order_book.add_order(&mut far).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(outcome.filled, Amount::new(4));
+        assert_eq!(outcome.discarded, Amount::new(1));
+        assert_eq!(outcome.resting, Amount::ZERO);
+        assert_eq!(outcome.trades[1].price, Amount::new(50));
+        assert_eq!(order_book.asks.len(), 0);
+        assert_eq!(order_book.bids.len(), 0);
+    }
+
+    #[test]
+    fn should_discard_ioc_remainder() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 2);
+        let mut bid = create_simple_bid(10, 5);
+        bid.time_in_force = TimeInForce::Ioc;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(outcome.filled, Amount::new(2));
+        assert_eq!(outcome.discarded, Amount::new(3));
+        assert_eq!(order_book.bids.len(), 0);
+    }
+
+    #[test]
+    fn should_reject_fok_that_cannot_fully_fill() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 2);
+        let mut killed = create_simple_bid(10, 3);
+        let mut filled = create_simple_bid(10, 2);
+        killed.time_in_force = TimeInForce::Fok;
+        filled.time_in_force = TimeInForce::Fok;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        let killed_outcome = order_book.add_order(&mut killed).unwrap();
+        let filled_outcome = order_book.add_order(&mut filled).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(killed_outcome.filled, Amount::ZERO);
+        assert_eq!(killed_outcome.discarded, Amount::new(3));
+        assert!(killed_outcome.trades.is_empty());
+        assert_eq!(filled_outcome.filled, Amount::new(2));
+        assert_eq!(order_book.pending_trades.len(), 1);
+        assert_eq!(order_book.asks.len(), 0);
+    }
+
+    #[test]
+    fn should_fill_fok_against_very_large_resting_orders() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut first = create_simple_ask(10, u64::MAX - 1);
+        let mut second = create_simple_ask(10, u64::MAX - 1);
+        let mut bid = create_simple_bid(10, 3);
+        first.id = String::from("first");
+        second.id = String::from("second");
+        bid.time_in_force = TimeInForce::Fok;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut first).unwrap();
+        order_book.add_order(&mut second).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(outcome.filled, Amount::new(3));
+        assert_eq!(outcome.trades[0].ask_id, String::from("first"));
+    }
+
+    #[test]
+    fn should_reject_crossing_post_only_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 2);
+        let mut bid = create_simple_bid(10, 1);
+        bid.id = String::from("2");
+        bid.post_only = PostOnly::Reject;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        let result = order_book.add_order(&mut bid);
+
+        //
+        // Assert
+        //
+        assert_eq!(
+            result.unwrap_err(),
+            OrderBookError::PostOnlyWouldCross(String::from("2"))
+        );
+        assert!(order_book.pending_trades.is_empty());
+        assert_eq!(order_book.bids.len(), 0);
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(2));
+    }
+
+    #[test]
+    fn should_reprice_crossing_post_only_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 2);
+        let mut bid = create_simple_bid(12, 1);
+        bid.post_only = PostOnly::Reprice;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.resting, Amount::new(1));
+        assert_eq!(order_book.best_bid().unwrap().price, Amount::new(9));
+    }
+
+    #[test]
+    fn should_reprice_post_only_order_by_tick_size() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        order_book.tick_size = Amount::new(5);
+        let mut ask = create_simple_ask(10, 2);
+        let mut bid = create_simple_bid(15, 1);
+        let mut top_bid = create_simple_bid(u64::MAX, 1);
+        let mut top_ask = create_simple_ask(5, 1);
+        bid.id = String::from("bid");
+        bid.post_only = PostOnly::Reprice;
+        top_bid.id = String::from("top_bid");
+        top_ask.id = String::from("top_ask");
+        top_ask.post_only = PostOnly::Reprice;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+        let repriced = order_book.bids.get("bid").unwrap().price;
+        order_book.cancel_order("1");
+        order_book.add_order(&mut top_bid).unwrap();
+        let rejected = order_book.add_order(&mut top_ask);
+
+        //
+        // Assert
+        //
+        assert_eq!(repriced, Amount::new(5));
+        assert_eq!(
+            rejected.unwrap_err(),
+            OrderBookError::PostOnlyWouldCross(String::from("top_ask"))
+        );
+    }
+
+    #[test]
+    fn should_skip_expired_counter_orders() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut stale = create_simple_ask(10, 2);
+        let mut live = create_simple_ask(11, 2);
+        let mut bid = create_simple_bid(11, 2);
+        stale.id = String::from("stale");
+        stale.expires_at = Some(100);
+        live.id = String::from("live");
+
+        //
+        // Act
+        //
+        order_book.add_order_at(&mut stale, 50).unwrap();
+        order_book.add_order_at(&mut live, 50).unwrap();
+        let outcome = order_book.add_order_at(&mut bid, 100).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(outcome.filled, Amount::new(2));
+        assert_eq!(outcome.trades[0].ask_id, String::from("live"));
+        assert_eq!(order_book.asks.len(), 0);
+    }
+
+    #[test]
+    fn should_purge_expired_orders() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(10, 2);
+        let mut ask = create_simple_ask(20, 2);
+        bid.expires_at = Some(100);
+        ask.expires_at = Some(200);
+
+        //
+        // Act
+        //
+        order_book.add_order_at(&mut bid, 50).unwrap();
+        order_book.add_order_at(&mut ask, 50).unwrap();
+        let expired = order_book.purge_expired(150);
+
+        //
+        // Assert
+        //
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].is_bid);
+        assert_eq!(order_book.bids.len(), 0);
+        assert_eq!(order_book.asks.len(), 1);
+    }
+
+    #[test]
+    fn should_activate_stop_when_trade_reaches_trigger() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut resting_bid = create_simple_bid(8, 5);
+        let mut stop = create_simple_ask(0, 2);
+        let mut ask = create_simple_ask(10, 1);
+        let mut bid = create_simple_bid(10, 1);
+        resting_bid.id = String::from("resting");
+        stop.id = String::from("stop");
+        stop.order_type = OrderType::Stop;
+        stop.trigger_price = Some(Amount::new(10));
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut resting_bid).unwrap();
+        let parked = order_book.add_order(&mut stop).unwrap();
+        let parked_triggers = order_book.triggers.len();
+        order_book.add_order(&mut ask).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(parked.resting, Amount::new(2));
+        assert_eq!(parked_triggers, 1);
+        assert!(order_book.triggers.is_empty());
+        assert_eq!(outcome.triggered.len(), 1);
+        assert_eq!(outcome.triggered[0].order_id, String::from("stop"));
+        assert_eq!(outcome.triggered[0].filled, Amount::new(2));
+        assert_eq!(outcome.triggered[0].trades[0].price, Amount::new(8));
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(3));
+    }
+
+    #[test]
+    fn should_rest_stop_limit_at_its_price_once_triggered() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut stop_limit = create_simple_bid(12, 2);
+        let mut ask = create_simple_ask(11, 1);
+        let mut bid = create_simple_bid(11, 1);
+        stop_limit.id = String::from("stop_limit");
+        stop_limit.order_type = OrderType::StopLimit;
+        stop_limit.trigger_price = Some(Amount::new(11));
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut stop_limit).unwrap();
+        order_book.add_order(&mut ask).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(outcome.triggered[0].resting, Amount::new(2));
+        assert_eq!(
+            order_book.best_bid().unwrap().id,
+            String::from("stop_limit")
+        );
+        assert_eq!(order_book.best_bid().unwrap().order_type, OrderType::Limit);
+    }
+
+    #[test]
+    fn should_replenish_iceberg_and_lose_priority() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut iceberg = create_simple_ask(10, 5);
+        let mut plain = create_simple_ask(10, 2);
+        let mut bid = create_simple_bid(10, 3);
+        iceberg.id = String::from("iceberg");
+        iceberg.display_quantity = Some(Amount::new(2));
+        plain.id = String::from("plain");
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut iceberg).unwrap();
+        order_book.add_order(&mut plain).unwrap();
+        let shown = order_book.best_ask().unwrap().quantity;
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        let ask_ids: Vec<&str> = outcome.trades.iter().map(|t| t.ask_id.as_str()).collect();
+        assert_eq!(shown, Amount::new(2));
+        assert_eq!(ask_ids, vec!["iceberg", "plain"]);
+        assert_eq!(order_book.best_ask().unwrap().id, String::from("plain"));
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(1));
+
+        let refilled = order_book.asks.get("iceberg").unwrap();
+        assert_eq!(refilled.quantity, Amount::new(2));
+        assert_eq!(refilled.reserve_quantity, Amount::new(1));
+    }
+
+    #[test]
+    fn should_hide_iceberg_reserve_in_public_view() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut iceberg = create_simple_ask(10, 5);
+        iceberg.display_quantity = Some(Amount::new(2));
+        let mut stop = create_simple_bid(12, 6);
+        stop.id = String::from("stop");
+        stop.order_type = OrderType::StopLimit;
+        stop.trigger_price = Some(Amount::new(12));
+        stop.display_quantity = Some(Amount::new(1));
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut iceberg).unwrap();
+        order_book.add_order(&mut stop).unwrap();
+        let public = order_book.public_view();
+
+        //
+        // Assert
+        //
+        assert_eq!(public.best_ask().unwrap().quantity, Amount::new(2));
+        assert_eq!(public.triggers[0].quantity, Amount::new(1));
+        assert_eq!(order_book.triggers[0].quantity, Amount::new(6));
+        assert_eq!(public.best_ask().unwrap().reserve_quantity, Amount::ZERO);
+        assert_eq!(
+            order_book.best_ask().unwrap().reserve_quantity,
+            Amount::new(3)
+        );
+    }
+
+    #[test]
+    fn should_amend_iceberg_across_its_reserve() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut iceberg = create_simple_ask(10, 10);
+        iceberg.id = String::from("iceberg");
+        iceberg.display_quantity = Some(Amount::new(2));
+        order_book.add_order(&mut iceberg).unwrap();
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Order {
-    pub id: String,
-    pub listing_id: String,
-    pub price: f64,
-    pub quantity: f64,
-    pub is_bid: bool,
-    pub created_at: String,
-    pub druid: Option<String>,
-    pub desired_listing_id: Option<String>,
-}
+        let decrease = OrderAmendment {
+            quantity: Some(Amount::new(5)),
+            ..Default::default()
+        };
+        let increase = OrderAmendment {
+            quantity: Some(Amount::new(7)),
+            ..Default::default()
+        };
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct OrderBook {
-    pub bids: Vec<Order>,
-    pub asks: Vec<Order>,
-    pub pending_trades: Vec<PendingTrade>,
-}
+        //
+        // Act
+        //
+        let decreased = order_book.amend_order_at("iceberg", decrease, 0).unwrap();
+        let increased = order_book.amend_order_at("iceberg", increase, 0).unwrap();
 
-/// Finds the index for an order to be inserted at based on the price
-///
-/// ### Arguments
-///
-/// * `prices` - A list of current orders
-/// * `price` - The price of the order to be inserted
-pub fn find_index_for_order(prices: &mut Vec<Order>, price: &f64) -> usize {
-    // If there are no orders, return 0
-    if prices.len() == 0 {
-        return 0;
+        //
+        // Assert
+        //
+        assert_eq!(decreased.quantity, Amount::new(2));
+        assert_eq!(decreased.reserve_quantity, Amount::new(3));
+        assert_eq!(increased.total_quantity(), Amount::new(7));
+
+        let resting = order_book.asks.get("iceberg").unwrap();
+        assert_eq!(resting.quantity, Amount::new(2));
+        assert_eq!(resting.reserve_quantity, Amount::new(5));
     }
 
-    let mut left = 0;
-    let mut right = prices.len() - 1;
+    #[test]
+    fn should_prevent_self_trades() {
+        //
+        // Arrange
+        //
+        let modes = [
+            SelfTradePrevention::CancelNewest,
+            SelfTradePrevention::CancelOldest,
+            SelfTradePrevention::CancelBoth,
+            SelfTradePrevention::Decrement,
+        ];
+
+        for mode in modes {
+            let mut order_book = OrderBook::new();
+            let mut own_ask = create_simple_ask(10, 2);
+            let mut other_ask = create_simple_ask(11, 5);
+            let mut bid = create_simple_bid(11, 3);
+            own_ask.id = String::from("own");
+            own_ask.owner = Some(String::from("alice"));
+            other_ask.id = String::from("other");
+            other_ask.owner = Some(String::from("bob"));
+            bid.owner = Some(String::from("alice"));
+            bid.self_trade_prevention = mode;
 
-    while left <= right {
-        let mid = (left + right) / 2;
-        let mid_price = &prices[mid].price;
+            //
+            // Act
+            //
+            order_book.add_order(&mut own_ask).unwrap();
+            order_book.add_order(&mut other_ask).unwrap();
+            let outcome = order_book.add_order(&mut bid).unwrap();
 
-        if mid_price == price {
-            return mid;
-        } else if mid_price < price {
-            left = mid + 1;
-        } else {
-            right = mid - 1;
+            //
+            // Assert
+            //
+            assert!(outcome.trades.iter().all(|t| t.ask_id != "own"));
+
+            match mode {
+                SelfTradePrevention::CancelNewest => {
+                    assert!(outcome.trades.is_empty());
+                    assert_eq!(outcome.discarded, Amount::new(3));
+                    assert!(order_book.asks.contains("own"));
+                }
+                SelfTradePrevention::CancelOldest => {
+                    assert_eq!(outcome.cancelled, vec![String::from("own")]);
+                    assert_eq!(outcome.filled, Amount::new(3));
+                    assert!(!order_book.asks.contains("own"));
+                }
+                SelfTradePrevention::CancelBoth => {
+                    assert_eq!(outcome.cancelled, vec![String::from("own")]);
+                    assert!(outcome.trades.is_empty());
+                    assert_eq!(outcome.discarded, Amount::new(3));
+                }
+                SelfTradePrevention::Decrement => {
+                    assert_eq!(outcome.cancelled, vec![String::from("own")]);
+                    assert_eq!(outcome.discarded, Amount::new(2));
+                    assert_eq!(outcome.filled, Amount::new(1));
+                    assert_eq!(outcome.trades[0].ask_id, String::from("other"));
+                }
+            }
         }
     }
 
-    left
-}
+    #[test]
+    fn should_keep_fok_whole_when_self_trade_prevention_stops_it() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut near = create_simple_ask(10, 3);
+        let mut own = create_simple_ask(11, 1);
+        let mut far = create_simple_ask(12, 5);
+        let mut bid = create_simple_bid(12, 5);
+        near.id = String::from("near");
+        near.owner = Some(String::from("bob"));
+        own.id = String::from("own");
+        own.owner = Some(String::from("alice"));
+        far.id = String::from("far");
+        far.owner = Some(String::from("bob"));
+        bid.owner = Some(String::from("alice"));
+        bid.time_in_force = TimeInForce::Fok;
 
-impl OrderBook {
-    pub fn new() -> Self {
-        OrderBook {
-            bids: Vec::new(),
-            asks: Vec::new(),
-            pending_trades: Vec::new(),
-        }
+        //
+        // Act
+        //
+        order_book.add_order(&mut near).unwrap();
+        order_book.add_order(&mut own).unwrap();
+        order_book.add_order(&mut far).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+
+        //
+        // Assert
+        //
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.discarded, Amount::new(5));
+        assert_eq!(order_book.asks.len(), 3);
     }
 
-    /// Matches an order with the lowest ask/highest bid, if possible. If not possible,
-    /// the order is added to the order book
-    ///
-    /// ### Arguments
-    ///
-    /// * `order` - The order to be matched
-    pub fn add_order(&mut self, order: &mut Order) {
-        let match_list = if order.is_bid {
-            &mut self.asks
-        } else {
-            &mut self.bids
-        };
-        let mut empty_orders = vec![Vec::<usize>::new(), Vec::<usize>::new()];
-        let mut match_idx = 0;
+    #[test]
+    fn should_restore_reserved_quantity_of_self_trade_cancelled_order() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut own = create_simple_ask(10, 5);
+        let mut other = create_simple_bid(10, 2);
+        let mut bid = create_simple_bid(10, 1);
+        own.id = String::from("own");
+        own.owner = Some(String::from("alice"));
+        other.id = String::from("other");
+        other.time_in_force = TimeInForce::Ioc;
+        bid.id = String::from("bid");
+        bid.owner = Some(String::from("alice"));
+        bid.self_trade_prevention = SelfTradePrevention::CancelOldest;
+        bid.time_in_force = TimeInForce::Ioc;
 
-        // Base case, where we're dealing with an empty orderbook
-        if match_list.len() == 0 {
-            self.insert_order_in_list(order.clone());
-            return;
-        }
+        //
+        // Act
+        //
+        order_book.add_order(&mut own).unwrap();
+        let matched = order_book.add_order(&mut other).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
+        let druid = matched.trades[0].druid.clone();
+        order_book
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
 
-        // The match order/list is the opposite side of the trade from the order.
-        // If the order is a bid, then the match order will be an ask
-        while match_idx < match_list.len() && order.quantity > 0.0 {
-            let match_order = &match_list[match_idx];
+        //
+        // Assert
+        //
+        assert_eq!(outcome.cancelled, vec![String::from("own")]);
+        assert!(order_book.held.is_empty());
+        assert_eq!(order_book.asks.get("own").unwrap().quantity, Amount::new(2));
+    }
 
-            if (order.is_bid && match_order.price <= order.price)
-                || (!order.is_bid && match_order.price >= order.price)
-            {
-                let quantity = match_order.quantity.min(order.quantity);
-                let bid_id = if order.is_bid {
-                    order.id.clone()
-                } else {
-                    match_order.id.clone()
-                };
-                let ask_id = if !order.is_bid {
-                    order.id.clone()
-                } else {
-                    match_order.id.clone()
-                };
-                let pending_trade = PendingTrade {
-                    bid_id,
-                    ask_id,
-                    quantity,
-                    price: match_order.price.min(order.price),
-                    created_at: String::from(""),
-                    druid: construct_druid(),
-                };
+    #[test]
+    fn should_move_confirmed_trades_out_of_pending() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 5);
+        let mut bid = create_simple_bid(10, 2);
 
-                // Handle pending trades and current orders
-                self.pending_trades.push(pending_trade);
-                match_list[match_idx].quantity -= quantity;
-                order.quantity -= quantity;
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+        let druid = order_book.pending_trades[0].druid.clone();
+        let submitted = order_book
+            .update_trade_status(&druid, TradeStatus::Submitted)
+            .unwrap();
+        let expired = order_book.update_trade_status(&druid, TradeStatus::Expired);
+        let confirmed = order_book
+            .update_trade_status(&druid, TradeStatus::Confirmed)
+            .unwrap();
 
-                // Add empty orders to the list for later clean up
-                if match_list[match_idx].quantity == 0.0 {
-                    if order.is_bid {
-                        empty_orders[0].push(match_idx);
-                    } else {
-                        empty_orders[1].push(match_idx);
-                    }
-                }
+        //
+        // Assert
+        //
+        assert_eq!(submitted.status, TradeStatus::Submitted);
+        assert_eq!(
+            expired.unwrap_err(),
+            OrderBookError::InvalidTradeTransition(TradeStatus::Submitted, TradeStatus::Expired)
+        );
+        assert_eq!(confirmed.status, TradeStatus::Confirmed);
+        assert!(order_book.pending_trades.is_empty());
+        assert_eq!(order_book.settled_trades.len(), 1);
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(3));
+    }
 
-                // Carry on to the next order
-                match_idx += 1;
-            } else {
-                self.insert_order_in_list(order.clone());
-                break;
-            }
-        }
+    #[test]
+    fn should_restore_resting_quantity_when_trade_fails() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 5);
+        let mut bid = create_simple_bid(10, 2);
+        bid.time_in_force = TimeInForce::Ioc;
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+        let druid = order_book.pending_trades[0].druid.clone();
+        let failed = order_book
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
+        let missing = order_book.update_trade_status(&druid, TradeStatus::Confirmed);
 
-        self.clean_up_empty_orders(empty_orders);
+        //
+        // Assert
+        //
+        assert_eq!(failed.status, TradeStatus::Failed);
+        assert_eq!(missing.unwrap_err(), OrderBookError::TradeNotFound(druid));
+        assert!(order_book.pending_trades.is_empty());
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(5));
     }
 
-    /// Inserts an order into the order book at the correct index
-    ///
-    /// ### Arguments
-    ///
-    /// * `order` - The order to be inserted
-    fn insert_order_in_list(&mut self, order: Order) {
-        let order_list = if order.is_bid {
-            &mut self.bids
-        } else {
-            &mut self.asks
-        };
-        let search_idx = find_index_for_order(order_list, &order.price);
-
-        let idx = match search_idx {
-            0 => 0,
-            _ => {
-                if order_list[search_idx].price > order.price && order.is_bid {
-                    search_idx + 1
-                } else {
-                    search_idx - 1
-                }
-            }
-        };
+    #[test]
+    fn should_restore_taker_quantity_when_trade_fails() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 3);
+        let mut bid = create_simple_bid(10, 5);
+        ask.id = String::from("ask");
+        bid.id = String::from("bid");
+
+        //
+        // Act
+        //
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+        let reserved = order_book.bids.get("bid").unwrap().reserved_quantity;
+        order_book.cancel_order("ask");
+        let druid = order_book.pending_trades[0].druid.clone();
+        order_book
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
 
-        order_list.insert(idx, order);
+        //
+        // Assert
+        //
+        assert_eq!(reserved, Amount::new(3));
+        assert!(order_book.pending_trades.is_empty());
+        assert_eq!(order_book.best_bid().unwrap().quantity, Amount::new(5));
+        assert!(order_book.best_bid().unwrap().reserved_quantity.is_zero());
     }
 
-    /// Removes orders from the order book if they have no quantity left
-    ///
-    /// ### Arguments
-    ///
-    /// * `empty_orders_list` - A list of indices for empty orders. A vector of 2 vectors, where
-    /// the first vector is for asks and the second vector is for bids
-    fn clean_up_empty_orders(&mut self, empty_orders_list: Vec<Vec<usize>>) {
-        empty_orders_list[0].iter().for_each(|idx| {
-            self.asks.remove(*idx);
-        });
+    #[test]
+    fn should_return_filled_order_to_book_with_original_priority() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut first_ask = create_simple_ask(10, 2);
+        first_ask.id = String::from("first");
+        let mut bid = create_simple_bid(10, 2);
+        bid.id = String::from("bid");
+        bid.time_in_force = TimeInForce::Ioc;
+        let mut second_ask = create_simple_ask(10, 3);
+        second_ask.id = String::from("second");
 
-        empty_orders_list[1].iter().for_each(|idx| {
-            self.bids.remove(*idx);
-        });
+        //
+        // Act
+        //
+        order_book.add_order(&mut first_ask).unwrap();
+        order_book.add_order(&mut bid).unwrap();
+        order_book.add_order(&mut second_ask).unwrap();
+        let held_ids: Vec<String> = order_book.held.iter().map(|o| o.id.clone()).collect();
+        let druid = order_book.pending_trades[0].druid.clone();
+        order_book
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
+
+        //
+        // Assert
+        //
+        assert_eq!(held_ids, vec![String::from("first"), String::from("bid")]);
+        assert!(order_book.held.is_empty());
+        assert!(order_book.bids.is_empty());
+        assert_eq!(order_book.best_ask().unwrap().id, String::from("first"));
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(2));
+        assert!(order_book.best_ask().unwrap().reserved_quantity.is_zero());
+        assert_eq!(order_book.asks.len(), 2);
     }
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Asset {
-    pub address: String,
-    pub name: String,
-    pub symbol: Option<String>,
-    pub total_supply: u64,
-    pub highest_bid: Option<String>,
-    pub lowest_ask: Option<String>,
-}
+    #[test]
+    fn should_rematch_restored_order_with_original_priority() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut bid = create_simple_bid(10, 5);
+        let mut market_ask = create_simple_ask(0, 5);
+        let mut ask = create_simple_ask(8, 3);
+        let mut stop = create_simple_ask(0, 1);
+        bid.id = String::from("bid");
+        market_ask.id = String::from("market");
+        market_ask.order_type = OrderType::Market;
+        ask.id = String::from("ask");
+        stop.id = String::from("stop");
+        stop.order_type = OrderType::Stop;
+        stop.trigger_price = Some(Amount::new(9));
 
-//------------- TESTS -------------//
+        //
+        // Act
+        //
+        order_book.add_order(&mut bid).unwrap();
+        let sequence = order_book.bids.get("bid").unwrap().sequence;
+        order_book.add_order(&mut market_ask).unwrap();
+        order_book.add_order(&mut ask).unwrap();
+        order_book.add_order(&mut stop).unwrap();
+        let druid = order_book.pending_trades[0].druid.clone();
+        order_book
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        //
+        // Assert
+        //
+        let restored = order_book.bids.get("bid").unwrap();
+        assert_eq!(restored.sequence, sequence);
+        assert_eq!(restored.quantity, Amount::new(1));
+        assert!(order_book.triggers.is_empty());
+        assert!(order_book.asks.is_empty());
+        assert_eq!(order_book.last_trade_price, Some(Amount::new(10)));
+    }
 
-    fn create_simple_bid(price: f64, quantity: f64) -> Order {
-        Order {
-            id: String::from("1"),
-            listing_id: String::from("1"),
-            price,
-            quantity,
-            is_bid: true,
-            created_at: String::from(""),
-            druid: None,
-            desired_listing_id: None,
-        }
+    #[test]
+    fn should_expire_unsettled_trades_after_timeout() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut ask = create_simple_ask(10, 5);
+        let mut bid = create_simple_bid(10, 5);
+        bid.time_in_force = TimeInForce::Ioc;
+        let now = 1_000;
+
+        //
+        // Act
+        //
+        order_book.add_order_at(&mut ask, now).unwrap();
+        order_book.add_order_at(&mut bid, now).unwrap();
+        let early = order_book.expire_trades(now + PENDING_TRADE_TIMEOUT - 1);
+        let reserved = order_book.held[0].reserved_quantity;
+        let expired = order_book.expire_trades(now + PENDING_TRADE_TIMEOUT);
+
+        //
+        // Assert
+        //
+        assert!(early.is_empty());
+        assert_eq!(reserved, Amount::new(5));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].status, TradeStatus::Expired);
+        assert!(order_book.pending_trades.is_empty());
+        assert!(order_book.held.is_empty());
+        assert_eq!(order_book.best_ask().unwrap().quantity, Amount::new(5));
     }
 
-    fn create_simple_ask(price: f64, quantity: f64) -> Order {
+    fn create_barter_ask(
+        id: &str,
+        listing_id: &str,
+        gives: u64,
+        desired_listing_id: &str,
+        wants: u64,
+    ) -> Order {
         Order {
-            id: String::from("1"),
-            listing_id: String::from("1"),
-            price,
-            quantity,
+            id: String::from(id),
+            listing_id: String::from(listing_id),
+            price: Amount::new(wants),
+            quantity: Amount::new(gives),
             is_bid: false,
             created_at: String::from(""),
             druid: None,
-            desired_listing_id: None,
+            desired_listing_id: Some(String::from(desired_listing_id)),
+            ..Default::default()
         }
     }
 
     #[test]
-    fn should_add_first_order() {
+    fn should_swap_reciprocal_barter_orders_across_books() {
         //
         // Arrange
         //
-        let mut order_book = OrderBook::new();
-        let mut order = create_simple_bid(1.0, 1.0);
+        let mut book_a = OrderBook::new();
+        let mut book_b = OrderBook::new();
+        let mut resting = create_barter_ask("b", "B", 4, "A", 2);
+        let mut unmatched = create_barter_ask("a1", "A", 1, "B", 4);
+        let mut incoming = create_barter_ask("a2", "A", 3, "B", 4);
 
         //
         // Act
         //
-        order_book.add_order(&mut order);
+        book_b.add_order(&mut resting).unwrap();
+        let waiting = book_a.match_barter(Some(&mut book_b), &mut unmatched, 0);
+        let outcome = book_a.match_barter(Some(&mut book_b), &mut incoming, 0);
 
         //
         // Assert
         //
-        assert_eq!(order_book.bids.len(), 1);
-        assert_eq!(order_book.bids[0].price, 1.0);
-        assert_eq!(order_book.bids[0].id, String::from("1"));
+        assert_eq!(waiting.resting, Amount::new(1));
+        assert_eq!(outcome.filled, Amount::new(2));
+        assert_eq!(outcome.resting, Amount::new(1));
+        assert_eq!(outcome.trades.len(), 1);
+        assert_eq!(
+            outcome.trades[0].legs,
+            vec![
+                TradeLeg {
+                    order_id: String::from("a2"),
+                    listing_id: String::from("A"),
+                    quantity: Amount::new(2),
+                },
+                TradeLeg {
+                    order_id: String::from("b"),
+                    listing_id: String::from("B"),
+                    quantity: Amount::new(4),
+                },
+            ]
+        );
+        assert_eq!(
+            book_a.pending_trades[0].druid,
+            book_b.pending_trades[0].druid
+        );
+        assert!(book_b.barters.is_empty());
+        assert_eq!(book_a.barters.len(), 2);
+        assert_eq!(book_a.held[0].id, String::from("a2"));
+        assert_eq!(book_b.held[0].id, String::from("b"));
     }
 
     #[test]
-    fn should_match_bid_to_ask() {
+    fn should_return_both_barter_orders_when_swap_fails() {
         //
         // Arrange
         //
-        let mut order_book = OrderBook::new();
-        let mut ask = create_simple_ask(1.5, 10.0);
-        let mut bid = create_simple_bid(2.0, 3.0);
+        let mut book_a = OrderBook::new();
+        let mut book_b = OrderBook::new();
+        let mut resting = create_barter_ask("b", "B", 4, "A", 2);
+        let mut incoming = create_barter_ask("a", "A", 2, "B", 4);
 
         //
         // Act
         //
-        order_book.add_order(&mut ask);
-        order_book.add_order(&mut bid);
+        book_b.add_order(&mut resting).unwrap();
+        book_a.match_barter(Some(&mut book_b), &mut incoming, 0);
+        let druid = book_a.pending_trades[0].druid.clone();
+        book_a
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
+        book_b
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
 
         //
         // Assert
         //
-        assert_eq!(order_book.bids.len(), 0);
-        assert_eq!(order_book.asks.len(), 1);
-        assert_eq!(order_book.asks[0].quantity, 7.0);
-        assert_eq!(order_book.pending_trades.len(), 1);
-        assert_eq!(order_book.pending_trades[0].quantity, 3.0);
-        assert_eq!(order_book.pending_trades[0].price, 1.5);
-        assert_eq!(order_book.pending_trades[0].bid_id, String::from("1"));
-        assert_eq!(order_book.pending_trades[0].ask_id, String::from("1"));
-        assert!(order_book.pending_trades[0].druid.len() > 0);
+        assert!(book_a.held.is_empty());
+        assert!(book_b.held.is_empty());
+        assert_eq!(book_a.barters[0].quantity, Amount::new(2));
+        assert_eq!(book_b.barters[0].quantity, Amount::new(4));
+        assert!(book_b.barters[0].reserved_quantity.is_zero());
     }
 
     #[test]
-    fn should_match_ask_to_bid() {
+    fn should_hold_only_the_swapped_part_of_unequal_barter_orders() {
         //
         // Arrange
         //
-        let mut order_book = OrderBook::new();
-        let mut bid = create_simple_bid(1.5, 10.0);
-        let mut ask = create_simple_ask(1.0, 3.0);
+        let mut book_a = OrderBook::new();
+        let mut book_b = OrderBook::new();
+        let mut resting = create_barter_ask("b", "B", 3, "A", 2);
+        let mut incoming = create_barter_ask("a", "A", 5, "B", 3);
 
         //
         // Act
         //
-        order_book.add_order(&mut bid);
-        order_book.add_order(&mut ask);
+        book_b.add_order(&mut resting).unwrap();
+        let outcome = book_a.match_barter(Some(&mut book_b), &mut incoming, 0);
+        let remainder = book_a.barters[0].clone();
+        let reserved = book_a.held[0].reserved_quantity;
+        let druid = book_a.pending_trades[0].druid.clone();
+        book_a
+            .update_trade_status(&druid, TradeStatus::Failed)
+            .unwrap();
 
         //
         // Assert
         //
-        assert_eq!(order_book.bids.len(), 1);
-        assert_eq!(order_book.bids[0].quantity, 7.0);
-        assert_eq!(order_book.asks.len(), 0);
-        assert_eq!(order_book.pending_trades.len(), 1);
-        assert_eq!(order_book.pending_trades[0].quantity, 3.0);
-        assert_eq!(order_book.pending_trades[0].price, 1.0);
-        assert_eq!(order_book.pending_trades[0].bid_id, String::from("1"));
-        assert_eq!(order_book.pending_trades[0].ask_id, String::from("1"));
-        assert!(order_book.pending_trades[0].druid.len() > 0);
+        assert_eq!(outcome.filled, Amount::new(2));
+        assert_eq!(outcome.resting, Amount::new(3));
+        assert_eq!(remainder.quantity, Amount::new(3));
+        assert_eq!(remainder.price, Amount::new(2));
+        assert_eq!(reserved, Amount::new(2));
+        assert!(book_a.held.is_empty());
+        assert_eq!(book_a.barters.len(), 1);
+        assert_eq!(book_a.barters[0].quantity, Amount::new(5));
+        assert_eq!(book_a.barters[0].price, Amount::new(3));
     }
 
     #[test]
-    fn should_handle_unmatched_orders() {
+    fn should_record_maker_and_taker_fees_on_trades() {
         //
         // Arrange
         //
         let mut order_book = OrderBook::new();
-        let mut bid = create_simple_bid(1.5, 10.0);
-        let mut ask = create_simple_ask(2.0, 3.0);
+        order_book.fees = FeeRates {
+            maker_bps: 10,
+            taker_bps: 20,
+            min_fee: Amount::ZERO,
+        };
+        let mut ask = create_simple_ask(500, 100);
+        let mut bid = create_simple_bid(500, 100);
 
         //
         // Act
         //
-        order_book.add_order(&mut bid);
-        order_book.add_order(&mut ask);
+        order_book.add_order(&mut ask).unwrap();
+        let outcome = order_book.add_order(&mut bid).unwrap();
 
         //
         // Assert
         //
-        assert_eq!(order_book.bids.len(), 1);
-        assert_eq!(order_book.bids[0].quantity, 10.0);
-        assert_eq!(order_book.bids[0].id, String::from("1"));
-        assert_eq!(order_book.asks.len(), 1);
-        assert_eq!(order_book.asks[0].quantity, 3.0);
-        assert_eq!(order_book.asks[0].id, String::from("1"));
-        assert_eq!(order_book.pending_trades.len(), 0);
+        assert_eq!(outcome.trades[0].maker_fee, Amount::new(50));
+        assert_eq!(outcome.trades[0].taker_fee, Amount::new(100));
+    }
+
+    #[test]
+    fn should_check_orders_against_listing_rules() {
+        //
+        // Arrange
+        //
+        let listing = Listing {
+            decimals: 2,
+            tick_size: Amount::new(5),
+            lot_size: Amount::new(10),
+            min_quantity: Amount::new(20),
+            min_notional: Amount::new(100),
+            ..Default::default()
+        };
+        let mut market = create_simple_bid(3, 30);
+        market.order_type = OrderType::Market;
+
+        //
+        // Act
+        //
+        let valid = listing.check_order(&create_simple_bid(500, 30));
+        let off_tick = listing.check_order(&create_simple_bid(501, 30));
+        let off_lot = listing.check_order(&create_simple_bid(500, 25));
+        let too_small = listing.check_order(&create_simple_bid(500, 10));
+        let too_cheap = listing.check_order(&create_simple_bid(5, 30));
+        let unpriced = listing.check_order(&market);
+
+        //
+        // Assert
+        //
+        assert_eq!(valid, Ok(()));
+        assert_eq!(
+            off_tick,
+            Err(ListingRuleError::OffTick(Amount::new(501), Amount::new(5)))
+        );
+        assert_eq!(
+            off_lot,
+            Err(ListingRuleError::OffLot(Amount::new(25), Amount::new(10)))
+        );
+        assert_eq!(
+            too_small,
+            Err(ListingRuleError::BelowMinQuantity(
+                Amount::new(10),
+                Amount::new(20)
+            ))
+        );
+        assert_eq!(
+            too_cheap,
+            Err(ListingRuleError::BelowMinNotional(
+                Amount::new(1),
+                Amount::new(100)
+            ))
+        );
+        assert_eq!(unpriced, Ok(()));
     }
 }
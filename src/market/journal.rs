@@ -0,0 +1,460 @@
+use crate::market::interfaces::{Order, OrderAmendment, OrderBook, PendingTrade, TradeStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Something that happened to a listing's orderbook. Events that come from a request
+/// record its inputs and the time it was handled at, so that replaying them through
+/// the orderbook reproduces it. The trades they create are recorded as facts
+/// alongside them, as their DRUIDs are random
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrderEvent {
+    /// The orderbook was created, or first journaled, in the given state
+    BookOpened { order_book: OrderBook },
+    /// An order arrived at the orderbook
+    OrderAccepted { order: Order, at: i64 },
+    /// Trades created by the preceding events
+    OrderMatched { trades: Vec<PendingTrade> },
+    /// A resting order was cancelled
+    OrderCancelled { order_id: String },
+    /// A resting order's price and/or quantity was amended
+    OrderAmended {
+        order_id: String,
+        amendment: OrderAmendment,
+        at: i64,
+    },
+    /// Expired orders were removed from the orderbook
+    OrdersExpired { order_ids: Vec<String>, at: i64 },
+    /// A pending trade moved to a new settlement state
+    TradeStatusChanged {
+        druid: String,
+        status: TradeStatus,
+        at: i64,
+    },
+    /// Pending trades past their settlement deadline expired
+    TradesExpired { druids: Vec<String>, at: i64 },
+    /// Barter orders in this orderbook were held against a swap, which may have been
+    /// matched from another listing's orderbook
+    BarterHeld {
+        held: Vec<Order>,
+        trade: PendingTrade,
+        /// What is left waiting of the held orders swapped only in part
+        #[serde(default)]
+        remaining: Vec<Order>,
+    },
+}
+
+/// An event in a listing's journal, at its position in that journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub listing_id: String,
+    pub offset: u64,
+    pub event: OrderEvent,
+}
+
+/// A listing's orderbook as it stood once every journal entry before `offset` had been
+/// applied. Replaying the entries from `offset` onwards brings it up to date
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub listing_id: String,
+    pub offset: u64,
+    pub order_book: OrderBook,
+    pub taken_at: i64,
+}
+
+impl Snapshot {
+    /// Creates a snapshot of an orderbook, covering every journal entry it has applied
+    ///
+    /// ### Arguments
+    ///
+    /// * `listing_id` - The ID of the listing the orderbook belongs to
+    /// * `order_book` - The orderbook to snapshot
+    /// * `taken_at` - The current Unix timestamp in seconds
+    pub fn new(listing_id: String, order_book: OrderBook, taken_at: i64) -> Self {
+        Self {
+            listing_id,
+            offset: order_book.journal_offset,
+            order_book,
+            taken_at,
+        }
+    }
+}
+
+/// Errors raised while replaying a journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The journal doesn't start by opening the orderbook
+    NotOpened,
+    /// An entry is missing, so the journal skips from one offset to another
+    Gap(u64, u64),
+    /// Replaying the entry at this offset didn't reproduce the trades it recorded
+    Diverged(u64),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::NotOpened => write!(f, "Journal doesn't open the orderbook"),
+            ReplayError::Gap(expected, found) => {
+                write!(f, "Journal skips from offset {expected} to {found}")
+            }
+            ReplayError::Diverged(offset) => {
+                write!(f, "Journal replay diverged at offset {offset}")
+            }
+        }
+    }
+}
+
+/// Journals the change from one state of an orderbook to the next, returning the
+/// entries to write. The event that caused the change comes first, followed by the
+/// trades it created. Orderbooks that have never been journaled are opened in their
+/// previous state first
+///
+/// ### Arguments
+///
+/// * `listing_id` - The ID of the listing the orderbook belongs to
+/// * `before` - The orderbook before the change
+/// * `after` - The orderbook after the change, whose journal offset is advanced
+/// * `cause` - The event that caused the change, if it came from a request
+pub fn journal_changes(
+    listing_id: &str,
+    before: &OrderBook,
+    after: &mut OrderBook,
+    cause: Option<OrderEvent>,
+) -> Vec<JournalEntry> {
+    let mut events = Vec::new();
+
+    if after.journal_offset == 0 && !matches!(cause, Some(OrderEvent::BookOpened { .. })) {
+        events.push(OrderEvent::BookOpened {
+            order_book: before.clone(),
+        });
+    }
+    events.extend(cause);
+
+    let known: HashSet<&str> = before
+        .pending_trades
+        .iter()
+        .map(|t| t.druid.as_str())
+        .collect();
+    let (barters, trades): (Vec<PendingTrade>, Vec<PendingTrade>) = after
+        .pending_trades
+        .iter()
+        .filter(|t| !known.contains(t.druid.as_str()))
+        .cloned()
+        .partition(|t| !t.legs.is_empty());
+
+    if !trades.is_empty() {
+        events.push(OrderEvent::OrderMatched { trades });
+    }
+
+    // An order swapped in parts is held once per swap, so held parts are told apart by
+    // what they reserve as well as by ID
+    let mut already_held: Vec<_> = before
+        .held
+        .iter()
+        .map(|o| (o.id.as_str(), o.reserved_quantity))
+        .collect();
+    let newly_held: Vec<&Order> = after
+        .held
+        .iter()
+        .filter(|o| {
+            match already_held
+                .iter()
+                .position(|h| *h == (o.id.as_str(), o.reserved_quantity))
+            {
+                Some(idx) => {
+                    already_held.remove(idx);
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect();
+
+    for trade in barters {
+        let in_trade = |o: &&Order| trade.legs.iter().any(|l| l.order_id == o.id);
+        let held: Vec<Order> = newly_held
+            .iter()
+            .copied()
+            .filter(in_trade)
+            .cloned()
+            .collect();
+        let remaining = after.barters.iter().filter(in_trade).cloned().collect();
+        events.push(OrderEvent::BarterHeld {
+            held,
+            trade,
+            remaining,
+        });
+    }
+
+    events
+        .into_iter()
+        .map(|event| {
+            let offset = after.journal_offset;
+            after.journal_offset += 1;
+
+            JournalEntry {
+                listing_id: listing_id.to_string(),
+                offset,
+                event,
+            }
+        })
+        .collect()
+}
+
+/// Rebuilds an orderbook by replaying its journal from the start. Trades are checked
+/// against the ones recorded, and given the DRUIDs they were originally matched with
+///
+/// ### Arguments
+///
+/// * `entries` - The orderbook's journal entries, in order
+pub fn replay(entries: &[JournalEntry]) -> Result<OrderBook, ReplayError> {
+    let (first, rest) = match entries.split_first() {
+        Some(split) => split,
+        None => return Err(ReplayError::NotOpened),
+    };
+
+    let mut order_book = match &first.event {
+        OrderEvent::BookOpened { order_book } => order_book.clone(),
+        _ => return Err(ReplayError::NotOpened),
+    };
+    order_book.journal_offset = first.offset + 1;
+
+    replay_onto(order_book, rest)
+}
+
+/// Replays journal entries onto an orderbook that is in the state the entries before
+/// them left it in
+///
+/// ### Arguments
+///
+/// * `order_book` - The orderbook to replay onto
+/// * `entries` - The journal entries to replay, in order
+pub fn replay_onto(
+    mut order_book: OrderBook,
+    entries: &[JournalEntry],
+) -> Result<OrderBook, ReplayError> {
+    let mut known: HashSet<String> = order_book
+        .pending_trades
+        .iter()
+        .chain(order_book.settled_trades.iter())
+        .map(|t| t.druid.clone())
+        .collect();
+
+    for entry in entries {
+        if entry.offset != order_book.journal_offset {
+            return Err(ReplayError::Gap(order_book.journal_offset, entry.offset));
+        }
+
+        apply_event(&mut order_book, &mut known, entry)?;
+        order_book.journal_offset = entry.offset + 1;
+    }
+
+    Ok(order_book)
+}
+
+/// Applies a single journal entry to an orderbook
+///
+/// ### Arguments
+///
+/// * `order_book` - The orderbook to apply the entry to
+/// * `known` - The DRUIDs of every trade replayed so far
+/// * `entry` - The entry to apply
+fn apply_event(
+    order_book: &mut OrderBook,
+    known: &mut HashSet<String>,
+    entry: &JournalEntry,
+) -> Result<(), ReplayError> {
+    // Requests that were refused are never journaled, so replaying one can't fail
+    match &entry.event {
+        OrderEvent::BookOpened { order_book: opened } => {
+            *order_book = opened.clone();
+        }
+        OrderEvent::OrderAccepted { order, at } => {
+            let mut order = order.clone();
+            if order.is_barter() {
+                order_book.queue_barter(&mut order, *at);
+            } else {
+                let _ = order_book.add_order_at(&mut order, *at);
+            }
+        }
+        OrderEvent::OrderMatched { trades } => {
+            let replayed: Vec<usize> = (0..order_book.pending_trades.len())
+                .filter(|i| !known.contains(&order_book.pending_trades[*i].druid))
+                .collect();
+
+            let matches = replayed.len() == trades.len()
+                && replayed.iter().zip(trades).all(|(i, recorded)| {
+                    let trade = &order_book.pending_trades[*i];
+                    (&trade.bid_id, &trade.ask_id, trade.price, trade.quantity)
+                        == (
+                            &recorded.bid_id,
+                            &recorded.ask_id,
+                            recorded.price,
+                            recorded.quantity,
+                        )
+                });
+            if !matches {
+                return Err(ReplayError::Diverged(entry.offset));
+            }
+
+            for (i, recorded) in replayed.into_iter().zip(trades) {
+                order_book.pending_trades[i] = recorded.clone();
+                known.insert(recorded.druid.clone());
+            }
+        }
+        OrderEvent::OrderCancelled { order_id } => {
+            order_book.cancel_order(order_id);
+        }
+        OrderEvent::OrderAmended {
+            order_id,
+            amendment,
+            at,
+        } => {
+            let _ = order_book.amend_order_at(order_id, amendment.clone(), *at);
+        }
+        OrderEvent::OrdersExpired { at, .. } => {
+            order_book.purge_expired(*at);
+        }
+        OrderEvent::TradeStatusChanged { druid, status, at } => {
+            let _ = order_book.update_trade_status_at(druid, *status, *at);
+        }
+        OrderEvent::TradesExpired { at, .. } => {
+            order_book.expire_trades(*at);
+        }
+        OrderEvent::BarterHeld {
+            held,
+            trade,
+            remaining,
+        } => {
+            for order in held {
+                order_book.barters.retain(|o| o.id != order.id);
+                order_book.held.push(order.clone());
+            }
+
+            for order in remaining {
+                let idx = order_book
+                    .barters
+                    .iter()
+                    .position(|o| o.sequence > order.sequence)
+                    .unwrap_or(order_book.barters.len());
+                order_book.barters.insert(idx, order.clone());
+            }
+
+            if !order_book
+                .pending_trades
+                .iter()
+                .any(|t| t.druid == trade.druid)
+            {
+                order_book.pending_trades.push(trade.clone());
+            }
+            known.insert(trade.druid.clone());
+        }
+    }
+
+    Ok(())
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::amount::Amount;
+    use mongodb::bson::to_document;
+
+    fn create_order(id: &str, price: u64, quantity: u64, is_bid: bool) -> Order {
+        Order {
+            id: id.to_string(),
+            listing_id: String::from("1"),
+            price: Amount::new(price),
+            quantity: Amount::new(quantity),
+            is_bid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_rebuild_order_book_from_journal_and_snapshot() {
+        //
+        // Arrange
+        //
+        let mut order_book = OrderBook::new();
+        let mut journal = Vec::new();
+        let mut record = |order_book: &mut OrderBook, event: OrderEvent| {
+            let before = order_book.clone();
+            match &event {
+                OrderEvent::OrderAccepted { order, at } => {
+                    order_book.add_order_at(&mut order.clone(), *at).unwrap();
+                }
+                OrderEvent::OrderCancelled { order_id } => {
+                    order_book.cancel_order(order_id).unwrap();
+                }
+                OrderEvent::TradeStatusChanged { druid, status, at } => {
+                    order_book
+                        .update_trade_status_at(druid, *status, *at)
+                        .unwrap();
+                }
+                OrderEvent::TradesExpired { at, .. } => {
+                    order_book.expire_trades(*at);
+                }
+                _ => unreachable!(),
+            }
+            journal.extend(journal_changes("1", &before, order_book, Some(event)));
+        };
+
+        for (id, price, quantity, is_bid) in [("a1", 10, 5, false), ("a2", 11, 3, false)] {
+            let order = create_order(id, price, quantity, is_bid);
+            record(&mut order_book, OrderEvent::OrderAccepted { order, at: 0 });
+        }
+        for (id, quantity) in [("b1", 4), ("b2", 2)] {
+            let order = create_order(id, 11, quantity, true);
+            record(&mut order_book, OrderEvent::OrderAccepted { order, at: 1 });
+        }
+
+        let druid = order_book.pending_trades[0].druid.clone();
+        let status = TradeStatus::Failed;
+        record(
+            &mut order_book,
+            OrderEvent::TradeStatusChanged {
+                druid,
+                status,
+                at: 2,
+            },
+        );
+        let order_id = String::from("a2");
+        record(&mut order_book, OrderEvent::OrderCancelled { order_id });
+        record(
+            &mut order_book,
+            OrderEvent::TradesExpired {
+                druids: Vec::new(),
+                at: i64::MAX,
+            },
+        );
+
+        //
+        // Act
+        //
+        let replayed = replay(&journal).unwrap();
+        let gap = replay(&[&journal[..2], &journal[3..]].concat());
+        let snapshot = Snapshot::new(String::from("1"), replay(&journal[..5]).unwrap(), 0);
+        let tail = &journal[snapshot.offset as usize..];
+        let resumed = replay_onto(snapshot.order_book, tail).unwrap();
+
+        //
+        // Assert
+        //
+        assert!(matches!(journal[0].event, OrderEvent::BookOpened { .. }));
+        assert_eq!(order_book.journal_offset, journal.len() as u64);
+        assert_eq!(
+            to_document(&replayed).unwrap(),
+            to_document(&order_book).unwrap()
+        );
+        assert_eq!(
+            to_document(&resumed).unwrap(),
+            to_document(&order_book).unwrap()
+        );
+        assert_eq!(gap.unwrap_err(), ReplayError::Gap(2, 3));
+    }
+}
@@ -1 +1,5 @@
+pub mod amount;
+pub mod fees;
 pub mod interfaces;
+pub mod journal;
+pub mod rings;
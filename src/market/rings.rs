@@ -0,0 +1,223 @@
+use crate::constants::MAX_RING_LENGTH;
+use crate::market::interfaces::{Order, OrderBook, PendingTrade};
+use std::collections::BTreeMap;
+
+/// Returns true if `from` gives the asset `to` wants, in at least the amount it wants
+///
+/// ### Arguments
+///
+/// * `from` - The barter order delivering the asset
+/// * `to` - The barter order receiving the asset
+fn delivers_to(from: &Order, to: &Order) -> bool {
+    let (gives, wants) = (from.gives(), to.wants());
+    gives.0 == wants.0 && gives.1 >= wants.1 && !from.same_owner(to)
+}
+
+/// Extends a path of barter orders depth-first until it closes into a ring. Only orders
+/// after the path's first order are added, so each ring is only found from one start
+///
+/// ### Arguments
+///
+/// * `orders` - Every waiting barter order
+/// * `path` - Indices into `orders` of the orders linked so far
+fn extend_ring(orders: &[&Order], path: &mut Vec<usize>) -> bool {
+    let (first, last) = (path[0], path[path.len() - 1]);
+
+    if path.len() > 1 && delivers_to(orders[last], orders[first]) {
+        return true;
+    }
+
+    if path.len() >= MAX_RING_LENGTH {
+        return false;
+    }
+
+    for next in first + 1..orders.len() {
+        if path.contains(&next) || !delivers_to(orders[last], orders[next]) {
+            continue;
+        }
+
+        path.push(next);
+        if extend_ring(orders, path) {
+            return true;
+        }
+        path.pop();
+    }
+
+    false
+}
+
+/// Finds the first ring of waiting barter orders across the given orderbooks in which
+/// each order gives what the next one wants, and the last gives what the first wants
+/// (A wants B, B wants C, C wants A). Rings have at most `MAX_RING_LENGTH` orders
+///
+/// ### Arguments
+///
+/// * `books` - The orderbooks to search, keyed by listing ID
+/// * `now` - The current Unix timestamp in seconds
+pub fn find_ring(books: &BTreeMap<String, OrderBook>, now: i64) -> Option<Vec<Order>> {
+    let orders: Vec<&Order> = books
+        .values()
+        .flat_map(|book| book.barters.iter())
+        .filter(|o| !o.is_expired(now))
+        .collect();
+
+    (0..orders.len()).find_map(|start| {
+        let mut path = vec![start];
+        extend_ring(&orders, &mut path)
+            .then(|| path.iter().map(|idx| orders[*idx].clone()).collect())
+    })
+}
+
+/// Repeatedly finds rings of barter orders across the given orderbooks, holding the part
+/// of each ring order its leg delivers against a single pending trade with one DRUID
+/// linking all its legs. Any part left over keeps waiting for another ring.
+/// The trade is recorded in the orderbook of every order in the ring. Returns the
+/// trades proposed
+///
+/// ### Arguments
+///
+/// * `books` - The orderbooks to search, keyed by listing ID
+/// * `now` - The current Unix timestamp in seconds
+pub fn match_rings(books: &mut BTreeMap<String, OrderBook>, now: i64) -> Vec<PendingTrade> {
+    let mut trades = Vec::new();
+
+    while let Some(ring) = find_ring(books, now) {
+        let trade = PendingTrade::barter(&ring, now);
+
+        // Each order delivers to the next, and only the part it delivers is held
+        for (order, to) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+            if let Some(book) = books.get_mut(&order.listing_id) {
+                book.hold_barter(&order.id, to);
+
+                if !book.pending_trades.iter().any(|t| t.druid == trade.druid) {
+                    book.pending_trades.push(trade.clone());
+                }
+            }
+        }
+
+        trades.push(trade);
+    }
+
+    trades
+}
+
+//------------- TESTS -------------//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::amount::Amount;
+    use crate::market::interfaces::TradeStatus;
+
+    fn create_barter_ask(id: &str, listing_id: &str, desired_listing_id: &str) -> Order {
+        create_sized_barter_ask(id, listing_id, 1, desired_listing_id, 1)
+    }
+
+    fn create_sized_barter_ask(
+        id: &str,
+        listing_id: &str,
+        gives: u64,
+        desired_listing_id: &str,
+        wants: u64,
+    ) -> Order {
+        Order {
+            id: String::from(id),
+            listing_id: String::from(listing_id),
+            price: Amount::new(wants),
+            quantity: Amount::new(gives),
+            is_bid: false,
+            created_at: String::from(""),
+            druid: None,
+            desired_listing_id: Some(String::from(desired_listing_id)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_propose_ring_trade_across_three_books() {
+        //
+        // Arrange
+        //
+        let mut books = BTreeMap::new();
+        for (id, listing_id, desired) in [("a", "A", "B"), ("b", "B", "C"), ("c", "C", "A")] {
+            let mut book = OrderBook::new();
+            book.add_order(&mut create_barter_ask(id, listing_id, desired))
+                .unwrap();
+            books.insert(String::from(listing_id), book);
+        }
+        books
+            .get_mut("A")
+            .unwrap()
+            .add_order(&mut create_barter_ask("lonely", "A", "D"))
+            .unwrap();
+
+        //
+        // Act
+        //
+        let trades = match_rings(&mut books, 0);
+
+        //
+        // Assert
+        //
+        assert_eq!(trades.len(), 1);
+        let order_ids: Vec<&str> = trades[0].legs.iter().map(|l| l.order_id.as_str()).collect();
+        assert_eq!(order_ids, vec!["a", "c", "b"]);
+        for book in books.values() {
+            assert_eq!(book.pending_trades[0].druid, trades[0].druid);
+            assert_eq!(book.held.len(), 1);
+        }
+        assert_eq!(books["A"].barters[0].id, String::from("lonely"));
+    }
+
+    #[test]
+    fn should_hold_only_what_each_ring_order_delivers() {
+        //
+        // Arrange
+        //
+        let mut books = BTreeMap::new();
+        for (id, listing_id, gives, desired, wants) in [
+            ("a", "A", 6, "B", 2),
+            ("b", "B", 5, "C", 3),
+            ("c", "C", 3, "A", 4),
+        ] {
+            let mut book = OrderBook::new();
+            book.add_order(&mut create_sized_barter_ask(
+                id, listing_id, gives, desired, wants,
+            ))
+            .unwrap();
+            books.insert(String::from(listing_id), book);
+        }
+
+        //
+        // Act
+        //
+        let trades = match_rings(&mut books, 0);
+        let held: Vec<Amount> = books
+            .values()
+            .map(|b| b.held[0].reserved_quantity)
+            .collect();
+        let left: Vec<Amount> = books
+            .values()
+            .map(|b| b.barters.first().map(|o| o.quantity).unwrap_or_default())
+            .collect();
+        let druid = trades[0].druid.clone();
+        for book in books.values_mut() {
+            book.update_trade_status(&druid, TradeStatus::Failed)
+                .unwrap();
+        }
+
+        //
+        // Assert
+        //
+        assert_eq!(trades.len(), 1);
+        assert_eq!(held, vec![Amount::new(4), Amount::new(2), Amount::new(3)]);
+        assert_eq!(left, vec![Amount::new(2), Amount::new(3), Amount::ZERO]);
+        for book in books.values() {
+            assert!(book.held.is_empty());
+        }
+        assert_eq!(books["A"].barters[0].quantity, Amount::new(6));
+        assert_eq!(books["A"].barters[0].price, Amount::new(2));
+        assert_eq!(books["B"].barters[0].quantity, Amount::new(5));
+        assert_eq!(books["C"].barters[0].quantity, Amount::new(3));
+    }
+}
@@ -0,0 +1,463 @@
+use crate::api::handlers::{
+    listing_send_handler, listings_handler, orders_pending_handler, orders_send_handler,
+    trades_status_handler,
+};
+use crate::api::routes::{
+    fees_report, listing_by_id, listing_send, orders_amend, orders_by_id, orders_cancel,
+    orders_send,
+};
+use crate::api::validation::FieldError;
+use crate::db::memory::InMemoryMarket;
+use crate::db::traits::MarketDatabase;
+use crate::market::amount::Amount;
+use crate::market::fees::FeeReport;
+use crate::market::interfaces::{
+    Listing, Order, OrderAmendment, OrderBook, OrderOutcome, PendingTrade, TradeStatus,
+};
+use async_trait::async_trait;
+use cuckoofilter::CuckooFilter;
+use futures::lock::Mutex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use valence_core::api::interfaces::CFilterConnection;
+use valence_core::api::responses::JsonReply;
+use valence_core::db::handler::KvStoreConnection;
+use warp::hyper::body::to_bytes;
+use warp::hyper::StatusCode;
+use warp::Reply;
+
+const LISTING_ID: &str = "a8f163782fb07c69f511248e";
+const BODY_LIMIT: u64 = 4096;
+
+/// A cache held in memory, standing in for Redis
+#[derive(Debug, Clone, Default)]
+struct MemoryCache {
+    data: HashMap<String, String>,
+}
+
+#[async_trait]
+impl KvStoreConnection for MemoryCache {
+    async fn init(_url: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self::default())
+    }
+
+    async fn set_data<T: Serialize + Send>(
+        &mut self,
+        key: &str,
+        value: T,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.data
+            .insert(key.to_string(), serde_json::to_string(&value)?);
+        Ok(())
+    }
+
+    async fn get_data<T: DeserializeOwned>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<T>, Box<dyn Error + Send + Sync>> {
+        match self.data.get(key) {
+            Some(value) => Ok(Some(serde_json::from_str(value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+type Connections = (
+    Arc<Mutex<InMemoryMarket>>,
+    Arc<Mutex<MemoryCache>>,
+    CFilterConnection,
+);
+
+fn create_connections() -> Connections {
+    (
+        Arc::new(Mutex::new(InMemoryMarket::new())),
+        Arc::new(Mutex::new(MemoryCache::default())),
+        Arc::new(Mutex::new(CuckooFilter::new())),
+    )
+}
+
+fn create_listing() -> Listing {
+    Listing {
+        _id: LISTING_ID.to_string(),
+        title: String::from("Asset_test"),
+        initial_price: Amount::new(10),
+        quantity: Amount::new(100),
+        ..Default::default()
+    }
+}
+
+fn create_bid(price: u64, quantity: u64) -> Order {
+    Order {
+        listing_id: LISTING_ID.to_string(),
+        price: Amount::new(price),
+        quantity: Amount::new(quantity),
+        is_bid: true,
+        ..Default::default()
+    }
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Reads a handler's reply into its status code and the content it carries
+async fn read_reply(reply: Result<JsonReply, JsonReply>) -> (StatusCode, Value) {
+    let response = match reply {
+        Ok(reply) | Err(reply) => reply.into_response(),
+    };
+    let status = response.status();
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+
+    (status, json["content"].clone())
+}
+
+/// Reads a route's response into its status code and the content it carries
+fn read_response(response: &warp::http::Response<warp::hyper::body::Bytes>) -> (StatusCode, Value) {
+    let json: Value = serde_json::from_slice(response.body()).unwrap();
+    (response.status(), json["content"].clone())
+}
+
+fn parse<T: DeserializeOwned>(content: Value) -> T {
+    serde_json::from_value(content).unwrap()
+}
+
+fn to_value<T: Serialize>(payload: T) -> Value {
+    serde_json::to_value(payload).unwrap()
+}
+
+//------------- HANDLER TESTS -------------//
+
+#[test]
+fn test_add_listing() {
+    //
+    // Arrange
+    //
+    let (db, cache, _) = create_connections();
+    let invalid = Listing {
+        _id: String::from("not-an-id"),
+        title: String::new(),
+        ..create_listing()
+    };
+
+    //
+    // Act
+    //
+    let (added, duplicate, rejected, listings) = block_on(async {
+        let added =
+            listing_send_handler(to_value(create_listing()), db.clone(), cache.clone()).await;
+        let duplicate =
+            listing_send_handler(to_value(create_listing()), db.clone(), cache.clone()).await;
+        let rejected = listing_send_handler(to_value(invalid), db.clone(), cache.clone()).await;
+        let listings = listings_handler(db.clone(), cache.clone()).await;
+
+        (
+            read_reply(added).await,
+            read_reply(duplicate).await,
+            read_reply(rejected).await,
+            read_reply(listings).await,
+        )
+    });
+
+    //
+    // Assert
+    //
+    let errors: Vec<FieldError> = parse(rejected.1);
+    let listings: Vec<Listing> = parse(listings.1);
+
+    assert_eq!(added.0, StatusCode::OK);
+    assert_eq!(duplicate.0, StatusCode::CONFLICT);
+    assert_eq!(rejected.0, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        errors.iter().map(|e| e.field.as_str()).collect::<Vec<_>>(),
+        vec!["_id", "title"]
+    );
+    assert_eq!(listings.len(), 1);
+    assert_eq!(listings[0]._id, LISTING_ID);
+}
+
+#[test]
+fn should_match_and_settle_orders_through_handlers() {
+    //
+    // Arrange
+    //
+    let (db, cache, cf) = create_connections();
+    block_on(async { db.lock().await.add_listing(create_listing()).await }).unwrap();
+    let unknown = Order {
+        listing_id: String::from("b8f163782fb07c69f511248e"),
+        ..create_bid(10, 5)
+    };
+
+    //
+    // Act
+    //
+    let (sent, rejected, pending, settled, missing, remaining) = block_on(async {
+        let sent = read_reply(
+            orders_send_handler(
+                to_value(create_bid(10, 5)),
+                db.clone(),
+                cache.clone(),
+                cf.clone(),
+            )
+            .await,
+        )
+        .await;
+        let rejected = read_reply(
+            orders_send_handler(to_value(unknown), db.clone(), cache.clone(), cf.clone()).await,
+        )
+        .await;
+        let pending = read_reply(
+            orders_pending_handler(
+                LISTING_ID.to_string(),
+                db.clone(),
+                cache.clone(),
+                cf.clone(),
+            )
+            .await,
+        )
+        .await;
+
+        let trades: Vec<PendingTrade> = parse(pending.1.clone());
+        let druid = trades[0].druid.clone();
+        let status = TradeStatus::Confirmed;
+        let settled = read_reply(
+            trades_status_handler(druid.clone(), status, db.clone(), cache.clone(), cf.clone())
+                .await,
+        )
+        .await;
+        let missing = read_reply(
+            trades_status_handler(druid, status, db.clone(), cache.clone(), cf.clone()).await,
+        )
+        .await;
+        let remaining = read_reply(
+            orders_pending_handler(
+                LISTING_ID.to_string(),
+                db.clone(),
+                cache.clone(),
+                cf.clone(),
+            )
+            .await,
+        )
+        .await;
+
+        (sent, rejected, pending, settled, missing, remaining)
+    });
+
+    //
+    // Assert
+    //
+    let outcome: OrderOutcome = parse(sent.1);
+    let errors: Vec<FieldError> = parse(rejected.1);
+    let pending: Vec<PendingTrade> = parse(pending.1);
+    let settled: PendingTrade = parse(settled.1);
+    let remaining: Vec<PendingTrade> = parse(remaining.1);
+
+    assert_eq!(sent.0, StatusCode::OK);
+    assert_eq!(outcome.filled, Amount::new(5));
+    assert_eq!(rejected.0, StatusCode::BAD_REQUEST);
+    assert_eq!(errors[0].field, "listing_id");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].druid, outcome.trades[0].druid);
+    assert_eq!(settled.status, TradeStatus::Confirmed);
+    assert_eq!(missing.0, StatusCode::NOT_FOUND);
+    assert!(remaining.is_empty());
+}
+
+//------------- ROUTE TESTS -------------//
+
+#[test]
+fn should_serve_listings_orders_and_fees_over_routes() {
+    //
+    // Arrange
+    //
+    let (db, cache, cf) = create_connections();
+    let listing_path = format!("/listings/{LISTING_ID}");
+    let orders_path = format!("/orders/{LISTING_ID}");
+
+    //
+    // Act
+    //
+    let (added, fetched, invalid, sent, amended, book, cancelled, recancelled, fees) =
+        block_on(async {
+            let added = warp::test::request()
+                .method("POST")
+                .path("/listings")
+                .json(&create_listing())
+                .reply(&listing_send(db.clone(), cache.clone(), BODY_LIMIT))
+                .await;
+            let by_id = listing_by_id(db.clone(), cache.clone(), cf.clone());
+            let fetched = warp::test::request()
+                .path(&listing_path)
+                .reply(&by_id)
+                .await;
+            let invalid = warp::test::request()
+                .path("/listings/not-an-id")
+                .reply(&by_id)
+                .await;
+
+            let sent = warp::test::request()
+                .method("POST")
+                .path("/orders")
+                .json(&create_bid(9, 5))
+                .reply(&orders_send(
+                    db.clone(),
+                    cache.clone(),
+                    cf.clone(),
+                    BODY_LIMIT,
+                ))
+                .await;
+            let outcome: OrderOutcome = parse(read_response(&sent).1);
+            let order_path = format!("{orders_path}/{}", outcome.order_id);
+
+            let amendment = OrderAmendment {
+                quantity: Some(Amount::new(3)),
+                ..Default::default()
+            };
+            let amended = warp::test::request()
+                .method("PUT")
+                .path(&order_path)
+                .json(&amendment)
+                .reply(&orders_amend(
+                    db.clone(),
+                    cache.clone(),
+                    cf.clone(),
+                    BODY_LIMIT,
+                ))
+                .await;
+            let book = warp::test::request()
+                .path(&orders_path)
+                .reply(&orders_by_id(db.clone(), cache.clone(), cf.clone()))
+                .await;
+
+            let cancel = orders_cancel(db.clone(), cache.clone(), cf.clone());
+            let cancelled = warp::test::request()
+                .method("DELETE")
+                .path(&order_path)
+                .reply(&cancel)
+                .await;
+            let recancelled = warp::test::request()
+                .method("DELETE")
+                .path(&order_path)
+                .reply(&cancel)
+                .await;
+            let fees = warp::test::request()
+                .path("/fees")
+                .reply(&fees_report(db.clone(), cache.clone(), cf.clone()))
+                .await;
+
+            (
+                read_response(&added),
+                read_response(&fetched),
+                read_response(&invalid),
+                read_response(&sent),
+                read_response(&amended),
+                read_response(&book),
+                read_response(&cancelled),
+                read_response(&recancelled),
+                read_response(&fees),
+            )
+        });
+
+    //
+    // Assert
+    //
+    let listing: Listing = parse(fetched.1);
+    let outcome: OrderOutcome = parse(sent.1);
+    let amended_order: Order = parse(amended.1);
+    let book: OrderBook = parse(book.1);
+    let cancelled_order: Order = parse(cancelled.1);
+    let fees: Vec<FeeReport> = parse(fees.1);
+
+    assert_eq!(added.0, StatusCode::OK);
+    assert_eq!(fetched.0, StatusCode::OK);
+    assert_eq!(listing.title, "Asset_test");
+    assert_eq!(invalid.0, StatusCode::BAD_REQUEST);
+    assert_eq!(sent.0, StatusCode::OK);
+    assert_eq!(outcome.resting, Amount::new(5));
+    assert_eq!(amended.0, StatusCode::OK);
+    assert_eq!(amended_order.quantity, Amount::new(3));
+    assert_eq!(book.best_bid().unwrap().quantity, Amount::new(3));
+    assert_eq!(cancelled.0, StatusCode::OK);
+    assert_eq!(cancelled_order.id, outcome.order_id);
+    assert_eq!(recancelled.0, StatusCode::NOT_FOUND);
+    assert_eq!(fees.len(), 1);
+}
+
+#[test]
+fn should_report_malformed_amounts_as_field_errors() {
+    //
+    // Arrange
+    //
+    let (db, cache, cf) = create_connections();
+    block_on(async { db.lock().await.add_listing(create_listing()).await }).unwrap();
+    let mut listing = to_value(create_listing());
+    listing["initial_price"] = serde_json::json!(-10);
+    let mut order = to_value(create_bid(10, 5));
+    order["price"] = serde_json::json!(-1);
+    order["quantity"] = serde_json::json!(2.5);
+    order["trigger_price"] = serde_json::json!("NaN");
+
+    //
+    // Act
+    //
+    let (listing, order, amendment) = block_on(async {
+        let listing = warp::test::request()
+            .method("POST")
+            .path("/listings")
+            .json(&listing)
+            .reply(&listing_send(db.clone(), cache.clone(), BODY_LIMIT))
+            .await;
+        let order = warp::test::request()
+            .method("POST")
+            .path("/orders")
+            .json(&order)
+            .reply(&orders_send(
+                db.clone(),
+                cache.clone(),
+                cf.clone(),
+                BODY_LIMIT,
+            ))
+            .await;
+        let amendment = warp::test::request()
+            .method("PUT")
+            .path(&format!("/orders/{LISTING_ID}/unknown"))
+            .json(&serde_json::json!({ "quantity": 0.5 }))
+            .reply(&orders_amend(
+                db.clone(),
+                cache.clone(),
+                cf.clone(),
+                BODY_LIMIT,
+            ))
+            .await;
+
+        (
+            read_response(&listing),
+            read_response(&order),
+            read_response(&amendment),
+        )
+    });
+
+    //
+    // Assert
+    //
+    let fields = |content: Value| {
+        parse::<Vec<FieldError>>(content)
+            .into_iter()
+            .map(|e| e.field)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(listing.0, StatusCode::BAD_REQUEST);
+    assert_eq!(fields(listing.1), vec!["initial_price"]);
+    assert_eq!(order.0, StatusCode::BAD_REQUEST);
+    assert_eq!(fields(order.1), vec!["price", "quantity", "trigger_price"]);
+    assert_eq!(amendment.0, StatusCode::BAD_REQUEST);
+    assert_eq!(fields(amendment.1), vec!["quantity"]);
+}
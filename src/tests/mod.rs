@@ -1,4 +1 @@
-#[cfg(test)]
-async fn test_add_listing() {
-    
-}
\ No newline at end of file
+pub mod interfaces;
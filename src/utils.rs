@@ -1,4 +1,6 @@
 use crate::constants::{DRUID_CHARSET, DRUID_LENGTH};
+use crate::db::errors::MarketError;
+use crate::market::amount::Amount;
 use crate::market::interfaces::{Order, OrderBook};
 use mongodb::bson::oid::ObjectId;
 use rand::Rng;
@@ -18,11 +20,13 @@ pub fn construct_druid() -> String {
     random_string
 }
 
-pub fn construct_mongodb_object_id(id: String) -> ObjectId {
-    match ObjectId::from_str(&id) {
-        Ok(object_id) => object_id,
-        Err(_) => ObjectId::new(),
-    }
+/// Parses a MongoDB object ID from its 24 character hex string
+///
+/// ### Arguments
+///
+/// * `id` - The ID to parse
+pub fn construct_mongodb_object_id(id: String) -> Result<ObjectId, MarketError> {
+    ObjectId::from_str(&id).map_err(|_| MarketError::InvalidId(id))
 }
 
 /// Constructs an initial orderbook for a new listing
@@ -32,16 +36,20 @@ pub fn construct_mongodb_object_id(id: String) -> ObjectId {
 /// * `listing_id` - The ID of the listing to create the orderbook for
 /// * `price` - The price of the initial ask order
 /// * `quantity` - The quantity of the initial ask order
+/// * `decimals` - The number of decimal places a base unit of the listing represents
 /// * `desired_listing_id` - The ID of the listing asset to trade the initial order with (optional)
-pub fn construct_initial_orderbook(listing_id: String, price: f64, quantity: f64, desired_listing_id: Option<String>) -> OrderBook {
+pub fn construct_initial_orderbook(listing_id: String, price: Amount, quantity: Amount, decimals: u32, desired_listing_id: Option<String>) -> OrderBook {
     let init_order = construct_initial_order(listing_id, price, quantity, desired_listing_id);
-    let asks = vec![init_order];
+    let mut order_book = OrderBook::new();
+    order_book.decimals = decimals;
 
-    OrderBook {
-        asks,
-        bids: Vec::new(),
-        pending_trades: Vec::new()
+    if init_order.is_barter() {
+        order_book.barters.push(init_order);
+    } else {
+        order_book.asks.insert(init_order);
     }
+
+    order_book
 }
 
 /// Constructs an initial order for a new listing
@@ -52,7 +60,7 @@ pub fn construct_initial_orderbook(listing_id: String, price: f64, quantity: f64
 /// * `price` - The price of the order
 /// * `quantity` - The quantity of the order
 /// * `desired_listing_id` - The ID of the listing to trade with (optional)
-fn construct_initial_order(listing_id: String, price: f64, quantity: f64, desired_listing_id: Option<String>) -> Order {
+fn construct_initial_order(listing_id: String, price: Amount, quantity: Amount, desired_listing_id: Option<String>) -> Order {
     // We can use the same function to get a base order ID as for a DRUID
     let id = construct_druid();
 
@@ -65,5 +73,6 @@ fn construct_initial_order(listing_id: String, price: f64, quantity: f64, desire
         created_at: Utc::now().to_string(),
         druid: None,
         desired_listing_id,
+        ..Default::default()
     }
 }